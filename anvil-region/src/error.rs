@@ -1,4 +1,5 @@
 use crate::position::RegionChunkPosition;
+use lz4_flex::block::DecompressError;
 use nbt::decode::TagDecodeError;
 use std::io;
 
@@ -18,7 +19,7 @@ pub enum ChunkReadError {
         /// Chunk maximum expected length.
         maximum_length: u32,
     },
-    /// Currently are only 2 types of compression: Gzip and Zlib.
+    /// Currently are only 4 types of compression: Gzip, Zlib, uncompressed and LZ4.
     ///
     /// This should not occur under normal conditions.
     ///
@@ -27,6 +28,9 @@ pub enum ChunkReadError {
         /// Compression scheme type id.
         compression_scheme: u8,
     },
+    /// Chunk is stored in an external `c.X.Z.mcc` file, but the region
+    /// was not loaded from a folder.
+    ExternalChunkUnavailable { position: RegionChunkPosition },
     /// I/O Error which happened while were reading chunk data from region file.
     IOError { io_error: io::Error },
     /// Error while decoding binary data to NBT tag.
@@ -35,6 +39,12 @@ pub enum ChunkReadError {
     ///
     /// Region file are corrupted or a developer error in the NBT library.
     TagDecodeError { tag_decode_error: TagDecodeError },
+    /// Error while decompressing LZ4 compressed chunk data.
+    ///
+    /// This should not occur under normal conditions.
+    ///
+    /// Region file are corrupted.
+    Lz4DecodeError { lz4_decode_error: DecompressError },
 }
 
 impl From<io::Error> for ChunkReadError {
@@ -49,10 +59,17 @@ impl From<TagDecodeError> for ChunkReadError {
     }
 }
 
+impl From<DecompressError> for ChunkReadError {
+    fn from(lz4_decode_error: DecompressError) -> Self {
+        ChunkReadError::Lz4DecodeError { lz4_decode_error }
+    }
+}
+
 /// Possible errors while saving the chunk.
 #[derive(Debug)]
 pub enum ChunkWriteError {
-    /// Chunk length exceeds 1 MB.
+    /// Chunk length exceeds 1 MB and the region has no folder
+    /// to store it in an external file.
     ///
     /// This should not occur under normal conditions.
     LengthExceedsMaximum {
@@ -48,7 +48,7 @@ impl<'a> RegionProvider<File> for FolderRegionProvider<'a> {
             .create(true)
             .open(region_path)?;
 
-        Region::load(position, file)
+        Region::load(position, file).map(|region| region.with_folder(self.folder_path))
     }
 }
 
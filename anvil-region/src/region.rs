@@ -1,14 +1,15 @@
 use crate::error::{ChunkReadError, ChunkWriteError};
 use crate::position::{RegionChunkPosition, RegionPosition};
 use bitvec::prelude::*;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use log::debug;
-use nbt::decode::{read_gzip_compound_tag, read_zlib_compound_tag};
+use nbt::decode::{read_compound_tag, read_gzip_compound_tag, read_zlib_compound_tag};
 use nbt::encode::write_zlib_compound_tag;
 use nbt::CompoundTag;
-use std::io;
-use std::io::{Cursor, Error, Read, Seek, SeekFrom, Write};
+use std::io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
 
 /// Amount of chunks in region.
 const REGION_CHUNKS: usize = 1024;
@@ -25,6 +26,19 @@ const CHUNK_MAXIMUM_BYTES_LENGTH: u32 = REGION_SECTOR_BYTES_LENGTH as u32 * 256;
 const GZIP_COMPRESSION_TYPE: u8 = 1;
 /// Zlib compression type value.
 const ZLIB_COMPRESSION_TYPE: u8 = 2;
+/// Uncompressed type value.
+const UNCOMPRESSED_TYPE: u8 = 3;
+/// LZ4 compression type value.
+const LZ4_COMPRESSION_TYPE: u8 = 4;
+/// Flag set on the compression type of chunks stored in an external `c.X.Z.mcc` file.
+const EXTERNAL_CHUNK_FLAG: u8 = 128;
+
+/// Magic of the blocks written by lz4-java's `LZ4BlockOutputStream`.
+const LZ4_BLOCK_MAGIC: &[u8; 8] = b"LZ4Block";
+/// LZ4 block which data are stored as is.
+const LZ4_BLOCK_METHOD_RAW: u8 = 0x10;
+/// LZ4 block which data are compressed.
+const LZ4_BLOCK_METHOD_LZ4: u8 = 0x20;
 
 /// Region represents a 32x32 group of chunks.
 pub struct Region<S> {
@@ -36,13 +50,75 @@ pub struct Region<S> {
     chunks_metadata: [ChunkMetadata; REGION_CHUNKS],
     /// Used sectors for chunks data.
     used_sectors: BitVec,
+    /// Folder where external chunk files are located.
+    ///
+    /// Without it oversized chunks can be neither read nor written.
+    folder_path: Option<PathBuf>,
 }
 
 impl<S> Region<S> {
+    /// Sets the folder where chunks too large for the region are stored in `c.X.Z.mcc` files.
+    pub fn with_folder(mut self, folder_path: &Path) -> Self {
+        self.folder_path = Some(folder_path.to_path_buf());
+        self
+    }
+
     /// Returns chunk metadata at specified coordinates.
     fn get_metadata(&self, position: &RegionChunkPosition) -> ChunkMetadata {
         self.chunks_metadata[position.metadata_index()]
     }
+
+    /// Returns path of the file where chunk data are stored if the chunk are too large for the region.
+    fn external_chunk_path(&self, position: &RegionChunkPosition) -> Option<PathBuf> {
+        let chunk_x = self.position.x * 32 + position.x as i32;
+        let chunk_z = self.position.z * 32 + position.z as i32;
+
+        self.folder_path
+            .as_ref()
+            .map(|folder_path| folder_path.join(format!("c.{}.{}.mcc", chunk_x, chunk_z)))
+    }
+}
+
+/// Decompresses data written by lz4-java's `LZ4BlockOutputStream`.
+///
+/// Stream consists of blocks with a header of magic, method,
+/// compressed length, decompressed length and checksum.
+fn decompress_lz4_blocks(compressed: &[u8]) -> Result<Vec<u8>, ChunkReadError> {
+    let mut cursor = Cursor::new(compressed);
+    let mut decompressed = Vec::new();
+
+    while (cursor.position() as usize) < compressed.len() {
+        let mut magic = [0u8; 8];
+        cursor.read_exact(&mut magic)?;
+
+        if &magic != LZ4_BLOCK_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid LZ4 block magic").into());
+        }
+
+        let method = cursor.read_u8()? & 0xF0;
+        let compressed_length = cursor.read_u32::<LittleEndian>()? as usize;
+        let decompressed_length = cursor.read_u32::<LittleEndian>()? as usize;
+        // Checksum is not verified, chunk data are validated by decoding NBT.
+        cursor.read_u32::<LittleEndian>()?;
+
+        // Empty block marks the end of the stream.
+        if decompressed_length == 0 {
+            break;
+        }
+
+        let mut block = vec![0u8; compressed_length];
+        cursor.read_exact(&mut block)?;
+
+        match method {
+            LZ4_BLOCK_METHOD_RAW => decompressed.extend_from_slice(&block),
+            LZ4_BLOCK_METHOD_LZ4 => {
+                decompressed.extend(lz4_flex::block::decompress(&block, decompressed_length)?)
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "Unknown LZ4 block method").into()),
+        }
+    }
+
+    Ok(decompressed)
 }
 
 /// Calculates used sectors.
@@ -119,6 +195,7 @@ impl<S: Read + Seek> Region<S> {
             source,
             chunks_metadata,
             used_sectors,
+            folder_path: None,
         };
 
         Ok(region)
@@ -152,11 +229,25 @@ impl<S: Read + Seek> Region<S> {
         let mut compressed_buffer = vec![0u8; (length - 1) as usize];
         self.source.read_exact(&mut compressed_buffer)?;
 
+        // Oversized chunks only keep the compression scheme in the region.
+        if compression_scheme & EXTERNAL_CHUNK_FLAG != 0 {
+            let external_chunk_path = self
+                .external_chunk_path(&position)
+                .ok_or(ChunkReadError::ExternalChunkUnavailable { position })?;
+
+            compressed_buffer = fs::read(external_chunk_path)?;
+        }
+
         let mut cursor = Cursor::new(&compressed_buffer);
 
-        match compression_scheme {
+        match compression_scheme & !EXTERNAL_CHUNK_FLAG {
             GZIP_COMPRESSION_TYPE => Ok(read_gzip_compound_tag(&mut cursor)?),
             ZLIB_COMPRESSION_TYPE => Ok(read_zlib_compound_tag(&mut cursor)?),
+            UNCOMPRESSED_TYPE => Ok(read_compound_tag(&mut cursor)?),
+            LZ4_COMPRESSION_TYPE => {
+                let decompressed_buffer = decompress_lz4_blocks(&compressed_buffer)?;
+                Ok(read_compound_tag(&mut Cursor::new(decompressed_buffer))?)
+            }
             _ => Err(ChunkReadError::UnsupportedCompressionScheme { compression_scheme }),
         }
     }
@@ -180,10 +271,32 @@ impl<S: Write + Seek> Region<S> {
         write_zlib_compound_tag(&mut buffer, &chunk_compound_tag)?;
 
         // 4 bytes for data length.
-        let length = (buffer.len() + 4) as u32;
+        let mut length = (buffer.len() + 4) as u32;
+        let external_chunk_path = self.external_chunk_path(&position);
 
         if length > CHUNK_MAXIMUM_BYTES_LENGTH {
-            return Err(ChunkWriteError::LengthExceedsMaximum { length });
+            let external_chunk_path = match external_chunk_path {
+                Some(external_chunk_path) => external_chunk_path,
+                None => return Err(ChunkWriteError::LengthExceedsMaximum { length }),
+            };
+
+            debug!(
+                target: "anvil-region",
+                "Region x: {}, z: {} chunk x: {}, z: {} with length {} is stored externally",
+                self.position.x, self.position.z, position.x, position.z, length
+            );
+
+            // Only compression scheme with external flag remains in the region.
+            fs::write(external_chunk_path, &buffer[1..])?;
+            buffer.truncate(1);
+            buffer[0] |= EXTERNAL_CHUNK_FLAG;
+            length = (buffer.len() + 4) as u32;
+        } else if let Some(external_chunk_path) = external_chunk_path {
+            // Remove data of the chunk from when it was oversized.
+            match fs::remove_file(external_chunk_path) {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
         }
 
         let mut metadata = self.find_place(&position, length)?;
@@ -448,13 +561,60 @@ mod tests {
     use crate::position::{RegionChunkPosition, RegionPosition};
     use crate::region;
     use crate::region::{
-        read_header, ChunkMetadata, Region, SeekExt, SeekWriteExt, REGION_HEADER_BYTES_LENGTH,
-        REGION_SECTOR_BYTES_LENGTH,
+        read_header, ChunkMetadata, Region, SeekExt, SeekWriteExt, EXTERNAL_CHUNK_FLAG,
+        LZ4_BLOCK_MAGIC, LZ4_BLOCK_METHOD_LZ4, LZ4_BLOCK_METHOD_RAW, LZ4_COMPRESSION_TYPE,
+        REGION_HEADER_BYTES_LENGTH, REGION_SECTOR_BYTES_LENGTH, ZLIB_COMPRESSION_TYPE,
     };
+    use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+    use nbt::encode::write_compound_tag;
     use nbt::CompoundTag;
+    use std::fs;
     use std::fs::File;
     use std::io::Cursor;
 
+    /// Creates region with a single chunk at 0, 0 which data are stored as is.
+    fn region_with_chunk(compression_scheme: u8, data: &[u8]) -> Region<Cursor<Vec<u8>>> {
+        let mut source = Vec::new();
+        let length = data.len() as u32 + 1;
+        let sectors = (length + 4) / REGION_SECTOR_BYTES_LENGTH as u32 + 1;
+
+        // Chunk data start right after header.
+        source.write_u32::<BigEndian>((2 << 8) | sectors).unwrap();
+        source.resize(REGION_HEADER_BYTES_LENGTH as usize, 0);
+
+        source.write_u32::<BigEndian>(length).unwrap();
+        source.write_u8(compression_scheme).unwrap();
+        source.extend_from_slice(data);
+        source.resize(
+            REGION_HEADER_BYTES_LENGTH as usize
+                + (sectors * REGION_SECTOR_BYTES_LENGTH as u32) as usize,
+            0,
+        );
+
+        Region::load(RegionPosition::new(0, 0), Cursor::new(source)).unwrap()
+    }
+
+    /// Creates block as written by lz4-java's `LZ4BlockOutputStream`.
+    fn lz4_block(method: u8, data: &[u8]) -> Vec<u8> {
+        let block_data = match method {
+            LZ4_BLOCK_METHOD_LZ4 => lz4_flex::block::compress(data),
+            _ => data.to_vec(),
+        };
+
+        let mut block = LZ4_BLOCK_MAGIC.to_vec();
+        // Method and compression level.
+        block.write_u8(method | 6).unwrap();
+        block
+            .write_u32::<LittleEndian>(block_data.len() as u32)
+            .unwrap();
+        block.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        // Checksum.
+        block.write_u32::<LittleEndian>(0).unwrap();
+        block.extend_from_slice(&block_data);
+
+        block
+    }
+
     #[test]
     fn test_header_read() {
         let expected_data = vec![
@@ -506,6 +666,115 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_chunk_lz4() {
+        let mut compound_tag = CompoundTag::new();
+        compound_tag.insert_str("test_str", "test");
+        compound_tag.insert_i32_vec("test_i32_vec", (0..3000).collect());
+
+        let mut data = Vec::new();
+        write_compound_tag(&mut data, &compound_tag).unwrap();
+
+        let (first_half, second_half) = data.split_at(data.len() / 2);
+        let mut compressed = lz4_block(LZ4_BLOCK_METHOD_LZ4, first_half);
+        compressed.extend(lz4_block(LZ4_BLOCK_METHOD_RAW, second_half));
+        // Empty block marks end of the stream.
+        compressed.extend(lz4_block(LZ4_BLOCK_METHOD_RAW, &[]));
+
+        let mut region = region_with_chunk(LZ4_COMPRESSION_TYPE, &compressed);
+        let read_compound_tag = region.read_chunk(RegionChunkPosition::new(0, 0)).unwrap();
+
+        assert_eq!(read_compound_tag.get_str("test_str").unwrap(), "test");
+        assert_eq!(
+            read_compound_tag.get_i32_vec("test_i32_vec").unwrap(),
+            &(0..3000).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_read_chunk_lz4_invalid_magic() {
+        let mut region = region_with_chunk(LZ4_COMPRESSION_TYPE, b"NotLZ4Block");
+
+        let load_error = region
+            .read_chunk(RegionChunkPosition::new(0, 0))
+            .err()
+            .unwrap();
+
+        match load_error {
+            ChunkReadError::IOError { .. } => {}
+            _ => panic!("Expected `IOError` but got `{:?}`", load_error),
+        }
+    }
+
+    #[test]
+    fn test_read_external_chunk_without_folder() {
+        let mut region = region_with_chunk(ZLIB_COMPRESSION_TYPE | EXTERNAL_CHUNK_FLAG, &[]);
+
+        let load_error = region
+            .read_chunk(RegionChunkPosition::new(0, 0))
+            .err()
+            .unwrap();
+
+        match load_error {
+            ChunkReadError::ExternalChunkUnavailable { position } => {
+                assert_eq!(position.x, 0);
+                assert_eq!(position.z, 0);
+            }
+            _ => panic!(
+                "Expected `ExternalChunkUnavailable` but got `{:?}`",
+                load_error
+            ),
+        }
+    }
+
+    #[test]
+    fn test_write_external_chunk() {
+        let folder_path = std::env::temp_dir().join("anvil-region-test-external-chunk");
+        fs::create_dir_all(&folder_path).unwrap();
+
+        let cursor = Cursor::new(Vec::new());
+        let mut region = Region::load(RegionPosition::new(1, -1), cursor)
+            .unwrap()
+            .with_folder(&folder_path);
+        let position = RegionChunkPosition::new(3, 4);
+        let external_chunk_path = folder_path.join("c.35.-28.mcc");
+
+        // Pseudo random values so compressed data still exceed maximum length.
+        let mut value = 1i64;
+        let i64_vec: Vec<_> = (0..200_000)
+            .map(|_| {
+                value ^= value << 13;
+                value ^= value >> 7;
+                value ^= value << 17;
+                value
+            })
+            .collect();
+
+        let mut write_compound_tag_1 = CompoundTag::new();
+        write_compound_tag_1.insert_i64_vec("test_i64_vec", i64_vec.clone());
+
+        region.write_chunk(position, write_compound_tag_1).unwrap();
+
+        assert!(external_chunk_path.exists());
+        assert_eq!(region.get_metadata(&position).sectors, 1);
+
+        let read_compound_tag = region.read_chunk(position).unwrap();
+        assert_eq!(
+            read_compound_tag.get_i64_vec("test_i64_vec").unwrap(),
+            &i64_vec
+        );
+
+        let mut write_compound_tag_2 = CompoundTag::new();
+        write_compound_tag_2.insert_str("test_str", "test");
+
+        region.write_chunk(position, write_compound_tag_2).unwrap();
+
+        assert!(!external_chunk_path.exists());
+
+        let read_compound_tag = region.read_chunk(position).unwrap();
+        assert_eq!(read_compound_tag.get_str("test_str").unwrap(), "test");
+    }
+
     #[test]
     fn test_iterate_region() {
         let file = File::open("test/region/r.0.0.mca").unwrap();
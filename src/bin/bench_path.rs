@@ -3,6 +3,7 @@ use std::fs::read_to_string;
 use std::time::Instant;
 
 use frightful_hobgoblin::pathfind::{pathfind, reachability_2d_from, reachability_from};
+use frightful_hobgoblin::route_planner::RoutePlanner;
 use frightful_hobgoblin::*;
 use itertools::Itertools;
 
@@ -35,6 +36,47 @@ fn main() {
         time.as_micros() as f32 / 1000. / i as f32
     );
 
+    let start = Instant::now();
+    let mut planner = RoutePlanner::default();
+    planner.update(&level);
+    let time = Instant::now() - start;
+    println!(
+        "Building route planner clusters took {}ms",
+        time.as_micros() as f32 / 1000.
+    );
+
+    let i = 2000;
+    let start = Instant::now();
+    for _ in 0..i {
+        std::hint::black_box(planner.search(
+            &level,
+            level.ground(ivec2(-50, 50)) + IVec3::Z,
+            level.ground(ivec2(100, -100)),
+            1,
+        ));
+    }
+    let time = Instant::now() - start;
+    println!(
+        "Hierarchical pathing {i} iterations, took {}ms/iter",
+        time.as_micros() as f32 / 1000. / i as f32
+    );
+
+    let i = 2000;
+    let start = Instant::now();
+    for _ in 0..i {
+        std::hint::black_box(planner.pathfind(
+            &level,
+            level.ground(ivec2(-50, 50)) + IVec3::Z,
+            level.ground(ivec2(100, -100)),
+            1,
+        ));
+    }
+    let time = Instant::now() - start;
+    println!(
+        "Cached pathing {i} iterations, took {}ms/iter",
+        time.as_micros() as f32 / 1000. / i as f32
+    );
+
     let i = 200;
     let start = Instant::now();
     for _ in 0..i {
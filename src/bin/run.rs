@@ -35,5 +35,8 @@ fn main() {
         map.save(heightmap_path);
     }
 
-    sim(level, config);
+    if let Err(err) = sim(level, config) {
+        eprintln!("{err}");
+        std::process::exit(1)
+    }
 }
@@ -1,3 +1,6 @@
+use crate::desire_lines::DesireLines;
+use crate::logistics::Pile;
+use crate::trees::Tree;
 use crate::*;
 use image::{Rgb, RgbImage};
 
@@ -9,7 +12,14 @@ pub enum Color {
     River,
     Path,
     Building,
+    Street,
+    Pile,
+    Tree,
     Grey(u8),
+    /// Grass shaded by slope, 128 is flat
+    Terrain(u8),
+    /// Desire line, brighter with more wear
+    Wear(u8),
 }
 
 pub struct MapImage {
@@ -37,7 +47,15 @@ impl MapImage {
                 Color::River => Rgb([100, 100, 255]),
                 Color::Path => Rgb([120, 120, 0]),
                 Color::Building => Rgb([200, 20, 0]),
+                Color::Street => Rgb([180, 170, 150]),
+                Color::Pile => Rgb([230, 160, 30]),
+                Color::Tree => Rgb([10, 80, 20]),
                 Color::Grey(value) => Rgb([value, value, value]),
+                Color::Terrain(shade) => {
+                    let scale = |value: f32| (value * shade as f32 / 128.).min(255.) as u8;
+                    Rgb([scale(60.), scale(140.), scale(50.)])
+                }
+                Color::Wear(wear) => Rgb([120 + wear / 2, 100 + wear / 3, 40]),
             },
         )
     }
@@ -79,4 +97,89 @@ impl MapImage {
             }
         }
     }
+
+    /// Hillshading, so that the terrain is readable below the settlement
+    pub fn relief(&mut self, level: &Level) {
+        for column in self.area {
+            let slope = level.height[column] * 2
+                - level.height[column - IVec2::X]
+                - level.height[column - IVec2::Y];
+            self.set(
+                column,
+                Color::Terrain((128 + slope * 12).clamp(40, 255) as u8),
+            )
+        }
+    }
+
+    pub fn desire_lines(&mut self, desire_lines: &DesireLines) {
+        for column in self.area {
+            let wear = desire_lines[column];
+            if wear > 0 {
+                self.set(column, Color::Wear((wear * 8).min(255) as u8))
+            }
+        }
+    }
+
+    /// Streets and blocked columns (mostly buildings, also fields, piles…)
+    pub fn blocked(&mut self, level: &Level) {
+        for column in self.area {
+            match level.blocked[column] {
+                Street => self.set(column, Color::Street),
+                Blocked => self.set(column, Color::Building),
+                Free => (),
+            }
+        }
+    }
+
+    /// Renders the current state of the simulation
+    pub fn settlement(&mut self, world: &mut World) {
+        let level = world.resource::<Level>();
+        self.relief(level);
+        self.water(level);
+        self.desire_lines(world.resource::<DesireLines>());
+        self.blocked(level);
+        for tree in world.query::<&Tree>().iter(world) {
+            for (pos, _) in &tree.blocks {
+                if self.area.contains(pos.truncate()) {
+                    self.set(pos.truncate(), Color::Tree)
+                }
+            }
+        }
+        for (pos, pile) in world.query::<(&Pos, &Pile)>().iter(world) {
+            let area = pile
+                .despawn_when_empty
+                .unwrap_or(Rect::new_centered(pos.block().truncate(), IVec2::ONE));
+            for column in area {
+                if self.area.contains(column) {
+                    self.set(column, Color::Pile)
+                }
+            }
+        }
+    }
+}
+
+/// Saves a top-down view of the settlement every [`Config::timelapse_interval`] ticks,
+/// to review changes to the generator without launching Minecraft
+pub fn timelapse_sys(world: &mut World) {
+    let Some(interval) = world
+        .resource::<Config>()
+        .timelapse_interval
+        .filter(|interval| *interval > 0)
+    else {
+        return;
+    };
+    let tick = world.resource::<CurrentTick>().0;
+    if tick % interval != 0 {
+        return;
+    }
+    let level = world.resource::<Level>();
+    let folder = level.path.join("timelapse");
+    let mut map = MapImage::new(level.area());
+    map.settlement(world);
+    rayon::spawn(move || {
+        std::fs::create_dir_all(&folder).unwrap();
+        map.buffer
+            .save(folder.join(format!("{tick:06}.png")))
+            .unwrap();
+    });
 }
@@ -1,11 +1,89 @@
 use crate::*;
+use itertools::Itertools;
 use sim::*;
 
+/// How far below the surface to look for structure blocks, e.g. for sunken floors
+const SCAN_DEPTH: i32 = 3;
+/// Columns of one structure may be this far apart
+const GAP: i32 = 2;
+/// Don't block the convex hull of sprawling structures such as long walls
+const MAX_HULL_FILL: i32 = 8;
+
 pub fn detect_existing_buildings_sys(mut level: ResMut<Level>) {
+    let mut structure = level.column_map::<bool, 1>(false);
     for column in level.area() {
-        match level(level.ground(column)) {
-            Full(
-                SmoothStone
+        if level(level.ground(column)) == Path {
+            level.blocked[column] = Street;
+        } else if man_made_column(&level, column) {
+            structure[column] = true;
+        }
+    }
+
+    let mut visited = level.column_map::<bool, 1>(false);
+    for column in level.area() {
+        if !structure[column] | visited[column] {
+            continue;
+        }
+        // Collect all nearby structure columns
+        visited[column] = true;
+        let mut columns = vec![column];
+        let mut i = 0;
+        while let Some(&current) = columns.get(i) {
+            i += 1;
+            for next in Rect::new_centered(current, IVec2::splat(GAP * 2 + 1)) {
+                if level.area().contains(next) && structure[next] & !visited[next] {
+                    visited[next] = true;
+                    columns.push(next);
+                }
+            }
+        }
+
+        let footprint = Polygon::convex_hull(columns.iter().copied());
+        let covered = footprint
+            .bounds()
+            .into_iter()
+            .filter(|&column| footprint.contains_convex(column))
+            .collect_vec();
+        let blocked = if covered.len() as i32 > columns.len() as i32 * MAX_HULL_FILL {
+            columns
+        } else {
+            covered
+        };
+        for column in blocked {
+            for x_off in -1..=1 {
+                for y_off in -1..=1 {
+                    level.blocked[column + ivec2(x_off, y_off)] = Blocked
+                }
+            }
+        }
+    }
+}
+
+/// Combines Minecraft's heightmaps with the blocks around the surface
+fn man_made_column(level: &Level, column: IVec2) -> bool {
+    let ground = level.height[column];
+    let stored = level.stored_height[column];
+
+    // Our heightmap ignores logs. Logs reaching above it without leaves covering
+    // them are walls or pillars rather than tree trunks.
+    let top = stored.motion_blocking_no_leaves;
+    if (top > ground + 1)
+        & (stored.world_surface == top)
+        & matches!(level(column.extend(top)), Log(.., Axis::Z))
+        & matches!(level(column.extend(top - 1)), Log(.., Axis::Z))
+    {
+        return true;
+    }
+
+    (ground - SCAN_DEPTH..=stored.world_surface.max(ground))
+        .any(|z| man_made(level(column.extend(z))))
+}
+
+fn man_made(block: Block) -> bool {
+    matches!(
+        block.dry(),
+        Full(
+            SmoothStone
                 | PolishedGranite
                 | PolishedDiorite
                 | PolishedAndesite
@@ -22,24 +100,26 @@ pub fn detect_existing_buildings_sys(mut level: ResMut<Level>) {
                 | PolishedBlackstone
                 | PolishedBlackstoneBrick
                 | MudBrick,
-            )
-            | Wool(..)
+        ) | Wool(..)
             | Carpet(..)
             | Stair(..)
             | Slab(..)
             | Fence(..)
             | FenceGate(..)
             | Glass(..)
+            | GlassPane(..)
             | Hay
-            | Rail(..) => {
-                for x_off in -1..=1 {
-                    for y_off in -1..=1 {
-                        level.blocked[column + ivec2(x_off, y_off)] = Blocked
-                    }
-                }
-            }
-            Path => level.blocked[column] = Street,
-            _ => (),
-        }
-    }
+            | Rail(..)
+            | Door(..)
+            | Trapdoor(..)
+            | Torch(..)
+            | Chest(..)
+            | Barrel
+            | CraftingTable
+            | Bookshelf
+            | Lectern(..)
+            | Bell(..)
+            // Persistent leaves are placed by players
+            | Leaves(_, None)
+    )
 }
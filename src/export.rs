@@ -0,0 +1,274 @@
+//! Export of the blocks changed by the simulation, to paste a settlement into other worlds
+//! or share it: as a Sponge schematic (v3, used by WorldEdit & co.) or as a vanilla structure.
+
+use std::{fs::File, path::Path};
+
+use bevy_ecs::{name::Name, query::With, world::World};
+use itertools::Itertools;
+use nbt::{CompoundTag, Tag};
+
+use crate::level::snbt;
+use crate::sim::{Pos, Villager, infinite_sim::Trader};
+use crate::*;
+
+pub struct Export<'a> {
+    level: &'a Level,
+    changes: Vec<(IVec3, Block, Option<CompoundTag>)>,
+    bounds: Cuboid,
+    /// Position and nbt, including the id
+    entities: Vec<(Vec3, CompoundTag)>,
+}
+
+impl<'a> Export<'a> {
+    /// Collects the blocks that differ from the loaded world, must be called before the level is saved
+    pub fn new(level: &'a Level) -> Self {
+        let changes = level.changed_blocks();
+        let first = changes
+            .first()
+            .map(|(pos, _, _)| *pos)
+            .unwrap_or_else(|| level.ground(level.area().center()));
+        let bounds = changes
+            .iter()
+            .fold(Cuboid::new(first, first), |bounds, (pos, _, _)| {
+                bounds.extend_to(*pos)
+            });
+        Self {
+            level,
+            changes,
+            bounds,
+            entities: Vec::new(),
+        }
+    }
+
+    pub fn add_villagers(&mut self, world: &mut World) {
+        let mut query = world.query_filtered::<(&Pos, &Name, Has<Trader>), With<Villager>>();
+        for (pos, name, is_trader) in query.iter(world) {
+            if !self.bounds.contains(pos.block()) {
+                continue;
+            }
+            let mut nbt = CompoundTag::new();
+            nbt.insert_str(
+                "id",
+                if is_trader {
+                    "minecraft:wandering_trader"
+                } else {
+                    "minecraft:villager"
+                },
+            );
+            if !is_trader {
+                let mut data = CompoundTag::new();
+                data.insert_str("type", self.level.biome_at(pos.block()).villager_type());
+                data.insert_str("profession", "minecraft:none");
+                data.insert_i32("level", 1);
+                nbt.insert_compound_tag("VillagerData", data);
+            }
+            if let Some(custom_name) =
+                snbt::parse(&format!("CustomName:{}", version().text(name.as_str())))
+            {
+                for (key, tag) in custom_name.iter() {
+                    nbt.insert(key, tag.clone());
+                }
+            }
+            self.entities.push((pos.0, nbt));
+        }
+        // Sorted for reproducible output
+        self.entities
+            .sort_by(|(a, _), (b, _)| a.to_array().partial_cmp(&b.to_array()).unwrap());
+    }
+
+    /// Sponge schematic v3. Contains the whole bounding box of the changed blocks,
+    /// including unchanged terrain.
+    pub fn schematic(&self) -> CompoundTag {
+        let Cuboid { min, max } = self.bounds;
+        let size = self.bounds.size();
+        let unknown = UNKNOWN_BLOCKS.read().unwrap();
+        let mut palette = HashMap::<Block, i32>::default();
+        let mut palette_nbt = CompoundTag::new();
+        let mut data = Vec::new();
+        // YZX order, in Minecraft coordinates
+        for z in min.z..=max.z {
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let block = (self.level)(ivec3(x, y, z));
+                    let next_id = palette.len() as i32;
+                    let id = *palette.entry(block).or_insert_with_key(|block| {
+                        palette_nbt.insert_i32(blockstate_string(block, &unknown), next_id);
+                        next_id
+                    });
+                    write_varint(&mut data, id);
+                }
+            }
+        }
+
+        let block_entities = self
+            .changes
+            .iter()
+            .filter_map(|(pos, _, block_entity)| {
+                let block_entity = block_entity.as_ref()?;
+                let mut nbt = CompoundTag::new();
+                nbt.insert("Pos", mc_pos(*pos - self.bounds.min));
+                nbt.insert_str("Id", namespaced(block_entity.get_str("id").unwrap()));
+                nbt.insert_compound_tag("Data", without(block_entity, &["id", "x", "y", "z"]));
+                Some(nbt)
+            })
+            .collect::<Vec<_>>();
+
+        let entities = self.entities.iter().map(|(pos, entity)| {
+            let pos = *pos - self.bounds.min.as_vec3();
+            let mut nbt = CompoundTag::new();
+            nbt.insert(
+                "Pos",
+                Tag::List(vec![
+                    Tag::Double(pos.x as f64),
+                    Tag::Double(pos.z as f64),
+                    Tag::Double(pos.y as f64),
+                ]),
+            );
+            nbt.insert_str("Id", entity.get_str("id").unwrap());
+            nbt.insert_compound_tag("Data", entity.clone());
+            nbt
+        });
+
+        let mut blocks = CompoundTag::new();
+        blocks.insert_compound_tag("Palette", palette_nbt);
+        blocks.insert("Data", Tag::ByteArray(data));
+        blocks.insert_compound_tag_vec("BlockEntities", block_entities);
+
+        let mut schematic = CompoundTag::new();
+        schematic.insert_i32("Version", 3);
+        schematic.insert_i32("DataVersion", version().0);
+        // Unsigned shorts
+        schematic.insert_i16("Width", size.x as u16 as i16);
+        schematic.insert_i16("Height", size.z as u16 as i16);
+        schematic.insert_i16("Length", size.y as u16 as i16);
+        schematic.insert("Offset", Tag::IntArray(vec![0, 0, 0]));
+        let mut metadata = CompoundTag::new();
+        metadata.insert("WorldOrigin", mc_pos(min));
+        schematic.insert_compound_tag("Metadata", metadata);
+        schematic.insert_compound_tag("Blocks", blocks);
+        schematic.insert_compound_tag_vec("Entities", entities);
+
+        let mut root = CompoundTag::new();
+        root.insert_compound_tag("Schematic", schematic);
+        root
+    }
+
+    /// Vanilla structure file. Only the changed blocks are included,
+    /// everything else in the bounding box acts like structure voids.
+    pub fn structure(&self) -> CompoundTag {
+        let unknown = UNKNOWN_BLOCKS.read().unwrap();
+        let mut palette = HashMap::<Block, i32>::default();
+        let mut palette_nbt = Vec::new();
+        let blocks = self
+            .changes
+            .iter()
+            .map(|(pos, block, block_entity)| {
+                let next_state = palette.len() as i32;
+                let state = *palette.entry(*block).or_insert_with_key(|block| {
+                    palette_nbt.push(block.to_nbt(&unknown));
+                    next_state
+                });
+                let mut nbt = CompoundTag::new();
+                nbt.insert("pos", int_list(*pos - self.bounds.min));
+                nbt.insert_i32("state", state);
+                if let Some(block_entity) = block_entity {
+                    nbt.insert_compound_tag("nbt", without(block_entity, &["x", "y", "z"]));
+                }
+                nbt
+            })
+            .collect::<Vec<_>>();
+
+        let entities = self.entities.iter().map(|(pos, entity)| {
+            let pos = *pos - self.bounds.min.as_vec3();
+            let mut nbt = CompoundTag::new();
+            nbt.insert(
+                "pos",
+                Tag::List(vec![
+                    Tag::Double(pos.x as f64),
+                    Tag::Double(pos.z as f64),
+                    Tag::Double(pos.y as f64),
+                ]),
+            );
+            nbt.insert("blockPos", int_list(pos.floor().as_ivec3()));
+            nbt.insert_compound_tag("nbt", entity.clone());
+            nbt
+        });
+
+        let mut nbt = CompoundTag::new();
+        nbt.insert_i32("DataVersion", version().0);
+        nbt.insert("size", int_list(self.bounds.size()));
+        nbt.insert_compound_tag_vec("palette", palette_nbt);
+        nbt.insert_compound_tag_vec("blocks", blocks);
+        nbt.insert_compound_tag_vec("entities", entities);
+        nbt
+    }
+
+    pub fn save_schematic(&self, path: impl AsRef<Path>) {
+        let mut file = File::create(path).expect("Failed to create schematic");
+        nbt::encode::write_gzip_compound_tag(&mut file, &self.schematic())
+            .expect("Failed to write schematic");
+    }
+
+    pub fn save_structure(&self, path: impl AsRef<Path>) {
+        let mut file = File::create(path).expect("Failed to create structure");
+        nbt::encode::write_gzip_compound_tag(&mut file, &self.structure())
+            .expect("Failed to write structure");
+    }
+}
+
+/// E.g. `minecraft:oak_stairs[facing=east,half=bottom]`
+fn blockstate_string(block: &Block, unknown: &UnknownBlocks) -> String {
+    let Blockstate(name, props) = block.blockstate(unknown);
+    let mut string = namespaced(&name);
+    if !props.is_empty() {
+        string.push('[');
+        string.push_str(
+            &props
+                .iter()
+                .map(|(prop, value)| format!("{prop}={value}"))
+                .join(","),
+        );
+        string.push(']');
+    }
+    string
+}
+
+fn namespaced(id: &str) -> String {
+    if id.contains(':') {
+        id.to_owned()
+    } else {
+        format!("minecraft:{id}")
+    }
+}
+
+fn without(nbt: &CompoundTag, keys: &[&str]) -> CompoundTag {
+    let mut filtered = CompoundTag::new();
+    for (key, tag) in nbt.iter() {
+        if !keys.contains(&key.as_str()) {
+            filtered.insert(key, tag.clone());
+        }
+    }
+    filtered
+}
+
+/// Minecraft coordinates (y up)
+fn mc_pos(pos: IVec3) -> Tag {
+    Tag::IntArray(vec![pos.x, pos.z, pos.y])
+}
+
+/// Minecraft coordinates (y up)
+fn int_list(pos: IVec3) -> Tag {
+    Tag::List(vec![Tag::Int(pos.x), Tag::Int(pos.z), Tag::Int(pos.y)])
+}
+
+fn write_varint(data: &mut Vec<i8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value = ((value as u32) >> 7) as i32;
+        if value == 0 {
+            data.push(byte as i8);
+            return;
+        }
+        data.push((byte | 0x80) as i8);
+    }
+}
@@ -75,8 +75,8 @@ impl Rect {
 
     pub fn overlap(self, other: Rect) -> Rect {
         Rect {
-            min: ivec2(self.min.x.max(other.min.x), self.min.x.max(other.min.y)),
-            max: ivec2(self.max.x.min(other.max.x), self.max.x.min(other.max.y)),
+            min: ivec2(self.min.x.max(other.min.x), self.min.y.max(other.min.y)),
+            max: ivec2(self.max.x.min(other.max.x), self.max.y.min(other.max.y)),
         }
     }
 
@@ -650,17 +650,64 @@ impl Polygon {
         inside
     }
 
-    pub fn iter(&self) -> PolygonIterator {
+    /// Convex hull in counterclockwise order
+    pub fn convex_hull(points: impl IntoIterator<Item = IVec2>) -> Polygon {
+        let mut points = points.into_iter().collect_vec();
+        points.sort_by_key(|p| (p.x, p.y));
+        points.dedup();
+        if points.len() < 3 {
+            return Polygon(points);
+        }
+        let turns_left = |a: IVec2, b: IVec2, c: IVec2| (b - a).perp_dot(c - a) > 0;
+        let mut hull: Vec<IVec2> = Vec::new();
+        for &point in &points {
+            while (hull.len() >= 2)
+                && !turns_left(hull[hull.len() - 2], hull[hull.len() - 1], point)
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        let lower_len = hull.len();
+        for &point in points.iter().rev().skip(1) {
+            while (hull.len() > lower_len)
+                && !turns_left(hull[hull.len() - 2], hull[hull.len() - 1], point)
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+        Polygon(hull)
+    }
+
+    /// Includes the border. Only valid for convex polygons in counterclockwise order
+    pub fn contains_convex(&self, column: IVec2) -> bool {
+        if self.0.len() < 3 {
+            return self.segments().any(|(a, b)| {
+                ((b - a).perp_dot(column - a) == 0) & Rect::new(a, b).contains(column)
+            }) | (self.0 == [column]);
+        }
+        self.segments()
+            .all(|(a, b)| (b - a).perp_dot(column - a) >= 0)
+    }
+
+    pub fn bounds(&self) -> Rect {
         let mut min = self.0[0];
         let mut max = self.0[0];
         for column in self.0.iter() {
             min = min.min(*column);
             max = max.max(*column);
         }
+        Rect { min, max }
+    }
+
+    pub fn iter(&self) -> PolygonIterator {
+        let bounds = self.bounds();
         PolygonIterator {
             polygon: self,
-            bounds: Rect { min, max },
-            current: min,
+            bounds,
+            current: bounds.min,
         }
     }
 
@@ -816,6 +863,10 @@ impl Cuboid {
         self.max - self.min + IVec3::splat(1)
     }
 
+    pub fn contains(self, pos: IVec3) -> bool {
+        pos.cmpge(self.min).all() & pos.cmple(self.max).all()
+    }
+
     pub fn d2(self) -> Rect {
         Rect {
             min: self.min.truncate(),
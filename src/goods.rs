@@ -11,6 +11,7 @@ pub enum Good {
     Wood,
     Soil,
     Brick,
+    Food,
 }
 
 impl Good {
@@ -20,6 +21,7 @@ impl Good {
             Self::Wood => Full(Wood(Oak)),
             Self::Soil => PackedMud,
             Self::Brick => Full(Brick),
+            Self::Food => Hay,
         }
     }
 }
@@ -123,7 +125,7 @@ pub fn goods_for_block(block: Block) -> Option<Stack> {
         }
     }
 
-    match block {
+    match block.dry() {
         // This doesn't follow Minecraft's exact ratios
         Log(..) => Some(Stack::new(Good::Wood, 2.)),
         Full(mat) => Some(Stack::new(get_blockmaterial(mat), 1.)),
@@ -8,10 +8,10 @@ use roof::build_roof;
 use self::{
     construction::RemoveWhenBlocked,
     desire_lines::{add_desire_line, DesireLines},
-    names::tavern_name,
     pathfind::pathfind_street,
     roof::{roof_shape, Shape},
     sim::{logistics::MoveTask, ConsItem, ConsList},
+    terraform::{WallCrest, make_retaining_wall},
 };
 use Biome::*;
 
@@ -26,6 +26,7 @@ struct Floor {
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum WallMaterial {
     Cobble,
+    Brick,
     Wattle,
     Logs,
     Planks,
@@ -49,13 +50,19 @@ pub fn house(
     dl: &mut DesireLines,
     untree: &mut Untree,
     area: Rect,
-    tavern: bool,
+    facing: Option<HDir>,
+    tavern: Option<&str>,
+    bricks: bool,
 ) -> (ConsList, House) {
     let inner = area.shrink(1);
 
     (level.blocked)(area, Free);
     // TODO: On sides that are wider than ~9 blocks, don't allow starting next to corner
-    let path = pathfind_street(level, area);
+    // The door goes on the side facing the road, unless there's no way from there
+    let path = facing
+        .map(|facing| pathfind_street(level, area, Some(facing)))
+        .filter(|path| path.success)
+        .unwrap_or_else(|| pathfind_street(level, area, None));
     for node in &path.path {
         for (x_off, y_off) in (-1..=1).cartesian_product(-1..=1) {
             level.blocked[node.pos.truncate() + ivec2(x_off, y_off)] = Street;
@@ -104,6 +111,7 @@ pub fn house(
             material: rand_weighted(&[
                 (1.0, WallMaterial::Cobble),
                 (log_weight_lower, WallMaterial::Logs),
+                (if bricks { 1.5 } else { 0. }, WallMaterial::Brick),
             ]),
             wood_framing: false,
         },
@@ -221,7 +229,7 @@ pub fn house(
     let (mut rec, house) = building(commands, level, untree, entrance, &floors, roof, chimney);
 
     let cursor = level.recording_cursor();
-    if tavern {
+    if let Some(name) = tavern {
         // Generate sign
         let door_dir = floors
             .iter()
@@ -253,7 +261,7 @@ pub fn house(
                 (1., Warped),
                 (1., Crimson),
             ]);
-            let nbt = sign_text(&tavern_name(), sign_type);
+            let nbt = sign_text(name, sign_type);
             level(pos, Sign(species, dir, sign_type), nbt);
             break;
         }
@@ -410,6 +418,7 @@ fn building(
         // Fill wall
         match floor.material {
             WallMaterial::Cobble => level.fill(wall, Full(Cobble)),
+            WallMaterial::Brick => level.fill(wall, Full(Brick)),
             WallMaterial::Wattle | WallMaterial::Planks => {
                 let mut wall_fill = Vec::new();
                 for pos in &wall {
@@ -503,11 +512,16 @@ fn building(
 
     // Chimney
     if let Some((chimney, dir)) = chimney {
+        let material = if floors[0].material == WallMaterial::Brick {
+            Brick
+        } else {
+            Cobble
+        };
         level.blocked[chimney + dir.offset(1, 0)] = Blocked;
         level.blocked[chimney + dir.offset(1, 1)] = Blocked;
         for z in floors[0].z - 4.. {
-            level((chimney + dir.offset(1, 0)).extend(z), Full(Cobble));
-            level((chimney + dir.offset(1, 1)).extend(z), Full(Cobble));
+            level((chimney + dir.offset(1, 0)).extend(z), Full(material));
+            level((chimney + dir.offset(1, 1)).extend(z), Full(material));
             if !roof.covers(chimney.extend(z - 1))
                 & !roof.covers((chimney + dir.offset(0, 1)).extend(z - 1))
             {
@@ -717,10 +731,30 @@ fn building(
     (rec, output)
 }
 
+/// How far the terraced yard extends past the walls
+const YARD_WIDTH: i32 = 3;
+
 fn foundation(level: &mut Level, untree: &mut Untree, area: Rect, floor: i32) -> ConsList {
     let cursor = level.recording_cursor();
     untree.remove_trees(level, area.grow(1));
+    let mut rec: ConsList = level.pop_recording(cursor).map(ConsItem::Set).collect();
 
+    // On slopes, terrace the yard instead of building up a bare stone skirt
+    // Neighboring buildings and streets are fine as long as the terrace doesn't reach them
+    let yard = area.grow(YARD_WIDTH);
+    let lowest = area.into_iter().map(|col| level.height[col]).min().unwrap();
+    // The wobbly wall can stray one column outside
+    let terrace = (floor - lowest >= 2)
+        & yard
+            .grow(1)
+            .into_iter()
+            .filter(|col| !area.contains(*col))
+            .all(|col| {
+                level.area().contains(col)
+                    && ((level.blocked[col] == Free) | (level.height[col] >= floor))
+            });
+
+    let cursor = level.recording_cursor();
     for z in (floor + 1..floor + 10).rev() {
         level.fill_at(area, z, Air)
     }
@@ -730,7 +764,7 @@ fn foundation(level: &mut Level, untree: &mut Untree, area: Rect, floor: i32) ->
         }
         level.height[col] = floor;
     }
-    let mut rec: ConsList = level.pop_recording(cursor).map(ConsItem::Set).collect();
+    rec.extend(level.pop_recording(cursor).map(ConsItem::Set));
     let cursor = level.recording_cursor();
     for col in area.border() {
         // TODO: if ground is too far down, try to make supports against the nearest wall instead
@@ -753,6 +787,26 @@ fn foundation(level: &mut Level, untree: &mut Untree, area: Rect, floor: i32) ->
     }
     rec.extend(level.pop_recording(cursor).map(ConsItem::Set));
 
+    // After the foundation, so that the footprint isn't filled with soil first
+    if terrace {
+        let lower = yard
+            .grow(1)
+            .into_iter()
+            .filter(|col| level.height[*col] < floor)
+            .collect_vec();
+        rec.extend(make_retaining_wall(
+            level,
+            &Polygon(yard.corners().collect()),
+            floor,
+            WallCrest::None,
+        ));
+        for col in lower {
+            if level.blocked[col] == Free {
+                level.blocked[col] = Blocked;
+            }
+        }
+    }
+
     rec
 }
 
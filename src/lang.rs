@@ -304,7 +304,7 @@ impl Lang {
         std::fs::create_dir_all(&data_path).unwrap();
         let blurbs = Tag::List((0..200).map(|_| self.spoken_sentence().into()).collect());
         let mut nbt = CompoundTag::new();
-        nbt.insert("DataVersion", DATA_VERSION);
+        nbt.insert("DataVersion", version().0);
         nbt.insert("data", {
             let mut nbt = CompoundTag::new();
             nbt.insert("contents", {
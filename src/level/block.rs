@@ -9,7 +9,7 @@ use std::{
 };
 
 pub use self::GroundPlant::*;
-use crate::{default, geometry::*, HashMap};
+use crate::{HashMap, Version, default, geometry::*, version};
 use enum_iterator::Sequence;
 use nbt::CompoundTag;
 use num_derive::FromPrimitive;
@@ -19,7 +19,7 @@ pub use BlockMaterial::*;
 pub use Color::*;
 pub use TreeSpecies::*;
 
-// TODO: Waterlogged blocks (incl kelp/kelp_plant); piglin head; button; sign
+// TODO: piglin head; button; sign
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Block {
     #[default]
@@ -31,6 +31,8 @@ pub enum Block {
     FenceGate(BlockMaterial, HDir, GateState),
     Ladder(HDir),
     Water,
+    /// Age if this is the top of the kelp
+    Kelp(Option<u8>),
     Lava,
     Ice,
     Dirt,
@@ -76,6 +78,8 @@ pub enum Block {
     SmithingTable,
     EnchantingTable,
     Bookshelf,
+    /// Whether it holds a book
+    Lectern(HDir, bool),
     DecoratedPot,
     Torch(Option<HDir>),
     IronBars,
@@ -92,6 +96,8 @@ pub enum Block {
     Stonecutter(HAxis),
     Smoker(HDir),
     BrewingStand,
+    /// Index into [`WATERLOGGED_BLOCKS`], use [`Block::waterlogged`] to construct
+    Waterlogged(u16),
     Other(u16),
 }
 
@@ -112,6 +118,16 @@ pub fn debug_read_unknown(index: u16) -> Blockstate {
     UNKNOWN_BLOCKS.read().unwrap().states[index as usize].clone()
 }
 
+/// Waterlogging doesn't fit into the 4 bytes of a block, so waterlogged blocks are interned
+/// the same way as unknown ones
+#[derive(Default)]
+pub struct WaterloggedBlocks {
+    map: HashMap<Block, u16>,
+    blocks: Vec<Block>,
+}
+
+pub static WATERLOGGED_BLOCKS: LazyLock<RwLock<WaterloggedBlocks>> = LazyLock::new(default);
+
 bitflags::bitflags! {
     #[derive(Copy,Clone, Debug, Eq, PartialEq, Hash)]
     pub struct DoorMeta: u8 {
@@ -180,7 +196,8 @@ pub enum GroundPlant {
     Cactus,
     Reeds,
     Pumpkin,
-    Crop(Crop),
+    // Age
+    Crop(Crop, u8),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -226,6 +243,24 @@ pub enum Crop {
     Beetroot,
 }
 
+impl Crop {
+    pub fn max_age(self) -> u8 {
+        match self {
+            Crop::Beetroot => 3,
+            _ => 7,
+        }
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            Crop::Wheat => "wheat",
+            Crop::Carrot => "carrots",
+            Crop::Potato => "potatoes",
+            Crop::Beetroot => "beetroots",
+        }
+    }
+}
+
 // Note: for dyes, id order is reversed
 #[derive(Debug, Copy, Clone, Eq, PartialEq, FromPrimitive, Hash)]
 #[repr(u8)]
@@ -397,7 +432,7 @@ impl Display for Blockstate {
 impl Blockstate {
     /// Doesn't set BlockStateTag because that shows an enchantment glint
     pub fn item_snbt(&self) -> String {
-        format!("{{id:\"{}\",Count:1}}", self.0)
+        format!("{{{}}}", version().item(&self.0, 1))
     }
 }
 
@@ -409,7 +444,12 @@ impl<Name: Into<Cow<'static, str>>> From<Name> for Blockstate {
 
 impl Block {
     // TODO: for fences & similar, emit block_ticks to make MC updateblockstates
+    /// In the format of the world's version
     pub fn blockstate(&self, unknown: &UnknownBlocks) -> Blockstate {
+        version().export_blockstate(self.current_blockstate(unknown))
+    }
+
+    fn current_blockstate(&self, unknown: &UnknownBlocks) -> Blockstate {
         match self {
             Air => "air".into(),
             Full(material) => match material {
@@ -434,6 +474,10 @@ impl Block {
             PackedMud => "packed_mud".into(),
             Bedrock => "bedrock".into(),
             Water => "water".into(),
+            Kelp(Some(age)) => {
+                Blockstate("kelp".into(), vec![("age".into(), age.to_string().into())])
+            }
+            Kelp(None) => "kelp_plant".into(),
             Lava => "lava".into(),
             Ice => "ice".into(),
             Log(species, log_type, axis) => match log_type {
@@ -474,7 +518,7 @@ impl Block {
                 },
             ),
             SmallPlant(plant) => match plant {
-                SmallPlant::Grass => "grass".into(),
+                SmallPlant::Grass => "short_grass".into(),
                 SmallPlant::Fern => "fern".into(),
                 SmallPlant::DeadBush => "dead_bush".into(),
                 SmallPlant::Dandelion => "dandelion".into(),
@@ -516,14 +560,10 @@ impl Block {
                 GroundPlant::Cactus => "cactus".into(),
                 GroundPlant::Reeds => "sugar_cane".into(),
                 GroundPlant::Pumpkin => "pumpkin".into(),
-                GroundPlant::Crop(crop) => match crop {
-                    Crop::Wheat => Blockstate("wheat".into(), vec![("age".into(), "7".into())]),
-                    Crop::Carrot => Blockstate("carrot".into(), vec![("age".into(), "7".into())]),
-                    Crop::Potato => Blockstate("potato".into(), vec![("age".into(), "7".into())]),
-                    Crop::Beetroot => {
-                        Blockstate("beetroot".into(), vec![("age".into(), "3".into())])
-                    }
-                },
+                GroundPlant::Crop(crop, age) => Blockstate(
+                    crop.to_str().into(),
+                    vec![("age".into(), age.to_string().into())],
+                ),
             },
             Fence(material) => match material {
                 Wood(species) => format!("{species}_fence").into(),
@@ -793,26 +833,41 @@ impl Block {
                 "smoker".into(),
                 vec![("facing".into(), dir.to_str().into())],
             ),
+            Lectern(dir, book) => Blockstate(
+                "lectern".into(),
+                vec![
+                    ("facing".into(), dir.to_str().into()),
+                    ("has_book".into(), book.to_string().into()),
+                ],
+            ),
             BrewingStand => "brewing_stand".into(),
+            Waterlogged(_) => {
+                let mut blockstate = self.dry().current_blockstate(unknown);
+                blockstate.1.push(("waterlogged".into(), "true".into()));
+                blockstate
+            }
             Other(index) => unknown.states[*index as usize].clone(),
         }
     }
 
     pub fn tile_entity_nbt(&self, pos: IVec3) -> Option<CompoundTag> {
-        match self {
-            Bell(..) => {
-                let mut nbt = CompoundTag::new();
-                nbt.insert_str("id", "bell");
-                Some(nbt)
-            }
-            WallBanner(..) => {
-                let mut nbt = CompoundTag::new();
-                nbt.insert_str("id", "banner");
-                Some(nbt)
-            }
+        match self.dry() {
+            Bell(..) => Some("bell"),
+            WallBanner(..) => Some("banner"),
+            Chest(..) => Some("chest"),
+            EnderChest(..) => Some("ender_chest"),
+            Barrel => Some("barrel"),
+            Smoker(..) => Some("smoker"),
+            BrewingStand => Some("brewing_stand"),
+            Lectern(..) => Some("lectern"),
+            DecoratedPot => Some("decorated_pot"),
+            Sign(_, _, SignType::WallHanging | SignType::Ceiling) => Some("hanging_sign"),
+            Sign(..) => Some("sign"),
             _ => None,
         }
-        .map(|mut nbt| {
+        .map(|id| {
+            let mut nbt = CompoundTag::new();
+            nbt.insert_str("id", id);
             nbt.insert_i32("x", pos.x);
             nbt.insert_i32("y", pos.z);
             nbt.insert_i32("z", pos.y);
@@ -822,11 +877,14 @@ impl Block {
 
     /// This is for loading of the structure block format and very much incomplete
     /// (and panics on invalid blocks)
-    pub fn from_nbt(nbt: &CompoundTag) -> Block {
+    pub fn from_nbt(nbt: &CompoundTag, version: Version) -> Block {
         let name = nbt.get_str("Name").expect("Invalid block: no name");
-        let name = name.strip_prefix("minecraft:").unwrap_or(name);
+        let name = version.import_block_name(name.strip_prefix("minecraft:").unwrap_or(name));
         let default_props = CompoundTag::new();
-        let props = nbt.get_compound_tag("Properties").unwrap_or(&default_props);
+        let props = &version.import_properties(
+            name,
+            nbt.get_compound_tag("Properties").unwrap_or(&default_props),
+        );
 
         fn slab(material: BlockMaterial, props: &CompoundTag) -> Block {
             match props.get_str("type").unwrap() {
@@ -918,6 +976,13 @@ impl Block {
             }
         }
 
+        fn crop(crop: Crop, props: &CompoundTag) -> Block {
+            GroundPlant(GroundPlant::Crop(
+                crop,
+                props.get_str("age").unwrap_or("0").parse().unwrap(),
+            ))
+        }
+
         fn log_axis(props: &CompoundTag) -> Axis {
             props.get_str("axis").unwrap().parse().unwrap()
         }
@@ -928,11 +993,14 @@ impl Block {
                 "air" | "cave_air" => Air,
                 // Let's ignore flowing water for now, maybe revise later
                 "water" => match props.get_str("level") {
-                    Ok("0") => Water,
+                    // Missing when written by us, Minecraft defaults to 0
+                    Ok("0") | Err(_) => Water,
                     _ => Air,
                 },
                 "ice" => Ice,
                 "tall_seagrass" => Water,
+                "kelp" => Kelp(Some(props.get_str("age").unwrap_or("0").parse().unwrap())),
+                "kelp_plant" => Kelp(None),
                 "stone" => Full(Stone),
                 "granite" => Full(Granite),
                 "diorite" => Full(Diorite),
@@ -969,7 +1037,7 @@ impl Block {
                 "mangrove_leaves" => leaves(Mangrove, props),
                 "cherry_leaves" => leaves(Cherry, props),
                 "flowering_azalea_leaves" => leaves(FloweringAzalea, props),
-                "grass" => SmallPlant(SmallPlant::Grass),
+                "short_grass" => SmallPlant(SmallPlant::Grass),
                 "fern" => SmallPlant(SmallPlant::Fern),
                 "dead_bush" => SmallPlant(SmallPlant::DeadBush),
                 "brown_mushroom" => SmallPlant(SmallPlant::BrownMushroom),
@@ -993,6 +1061,11 @@ impl Block {
                 "rose_bush" => TallPlant(TallPlant::Rose, half(props)),
                 "peony" => TallPlant(TallPlant::Peony, half(props)),
                 // "tall_seagrass" => TallPlant(TallPlant::Seagrass, half(props)),
+                "farmland" => Farmland,
+                "wheat" => crop(Crop::Wheat, props),
+                "carrots" => crop(Crop::Carrot, props),
+                "potatoes" => crop(Crop::Potato, props),
+                "beetroots" => crop(Crop::Beetroot, props),
                 "snow" => SnowLayer, // Todo: store layer
                 "snow_block" => SnowBlock,
                 "powdered_snow" => PowderedSnow,
@@ -1116,34 +1189,38 @@ impl Block {
                 "polished_blackstone_button" => button(PolishedBlackstone, props),
                 "ladder" => Ladder(facing(props)),
                 "smoker" => Smoker(facing(props)),
+                "lectern" => Lectern(
+                    facing(props),
+                    matches!(props.get_str("has_book"), Ok("true")),
+                ),
                 "brewing_stand" => BrewingStand,
                 _ => return None,
             })
         }
 
         if let Some(known) = known_block(name, props) {
-            return known;
+            return if matches!(props.get_str("waterlogged"), Ok("true")) {
+                known.waterlogged()
+            } else {
+                known
+            };
         }
 
         let blockstate = Blockstate(
             name.to_owned().into(),
-            if let Ok(props) = nbt.get_compound_tag("Properties") {
-                props
-                    .iter()
-                    .map(|(name, value)| {
-                        (
-                            name.clone().into(),
-                            if let nbt::Tag::String(value) = value {
-                                value.clone().into()
-                            } else {
-                                panic!("Non-string blockstate value")
-                            },
-                        )
-                    })
-                    .collect()
-            } else {
-                Vec::new()
-            },
+            props
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.clone().into(),
+                        if let nbt::Tag::String(value) = value {
+                            value.clone().into()
+                        } else {
+                            panic!("Non-string blockstate value")
+                        },
+                    )
+                })
+                .collect(),
         );
 
         thread_local! {
@@ -1186,8 +1263,9 @@ impl Block {
     pub fn solid(self) -> bool {
         // Todo: expand this
         !matches!(
-            self,
+            self.dry(),
             Air | Water
+                | Kelp(..)
                 | Lava
                 | SmallPlant(..)
                 | TallPlant(..)
@@ -1221,12 +1299,20 @@ impl Block {
         )
     }
 
+    /// Shape depends on neighboring blocks
+    pub fn neighbor_dependent(self) -> bool {
+        matches!(
+            self.dry(),
+            Fence(..) | FenceGate(..) | GlassPane(..) | IronBars | Stair(..)
+        )
+    }
+
     pub fn solid_underside(self) -> bool {
-        self.solid() & !matches!(self, Slab(_, Top))
+        self.solid() & !matches!(self.dry(), Slab(_, Top))
     }
 
     pub fn walkable(self) -> bool {
-        self.solid() | matches!(self, Ladder(..))
+        self.solid() | self.climbable()
     }
 
     pub fn soil(self) -> bool {
@@ -1258,11 +1344,93 @@ impl Block {
     }
 
     pub fn no_pathing(self) -> bool {
-        matches!(self, Water | Lava | GroundPlant(Cactus))
+        matches!(self.dry(), Water | Kelp(..) | Lava | GroundPlant(Cactus))
     }
 
     pub fn climbable(self) -> bool {
-        matches!(self, Ladder(..))
+        matches!(self.dry(), Ladder(..))
+    }
+
+    /// Whether pathfinding treats both blocks the same
+    pub fn same_for_pathing(self, other: Block) -> bool {
+        let properties = |block: Block| {
+            (
+                block.solid(),
+                block.climbable(),
+                block.no_pathing(),
+                matches!(block.dry(), Water),
+            )
+        };
+        properties(self) == properties(other)
+    }
+
+    /// Water source, or a block submerged in it
+    pub fn contains_water(self) -> bool {
+        matches!(
+            self,
+            Water
+                | Kelp(..)
+                | SmallPlant(SmallPlant::Seagrass)
+                | TallPlant(TallPlant::Seagrass, _)
+                | Waterlogged(_)
+        )
+    }
+
+    /// Whether this block can be placed in water while keeping it.
+    /// Blocks that are always submerged (e.g. kelp) don't count.
+    pub fn waterloggable(self) -> bool {
+        matches!(
+            self,
+            Slab(..)
+                | Stair(..)
+                | Fence(..)
+                | Ladder(..)
+                | Leaves(..)
+                | MangroveRoots
+                | GlassPane(..)
+                | IronBars
+                | Chest(..)
+                | EnderChest(..)
+                | DecoratedPot
+                | Trapdoor(..)
+                | Sign(..)
+                | Rail(..)
+        )
+    }
+
+    /// Submerges the block, if it can be waterlogged
+    pub fn waterlogged(self) -> Block {
+        if !self.waterloggable() {
+            return self;
+        }
+        if let Some(&index) = WATERLOGGED_BLOCKS.read().unwrap().map.get(&self) {
+            return Waterlogged(index);
+        }
+        let mut waterlogged = WATERLOGGED_BLOCKS.write().unwrap();
+        if let Some(&index) = waterlogged.map.get(&self) {
+            return Waterlogged(index);
+        }
+        let index = waterlogged.blocks.len() as u16;
+        waterlogged.map.insert(self, index);
+        waterlogged.blocks.push(self);
+        Waterlogged(index)
+    }
+
+    /// Waterlogs the block if it replaces water, so placing it doesn't leave a dry pocket
+    pub fn waterlogged_in(self, previous: Block) -> Block {
+        if previous.contains_water() {
+            self.waterlogged()
+        } else {
+            self
+        }
+    }
+
+    /// The block without water
+    pub fn dry(self) -> Block {
+        match self {
+            Waterlogged(index) => WATERLOGGED_BLOCKS.read().unwrap().blocks[index as usize],
+            _ => self,
+        }
     }
 
     fn map_orientation(
@@ -1278,6 +1446,7 @@ impl Block {
             _ => dir,
         };
         match self {
+            Waterlogged(_) => self.dry().map_orientation(map_axis, hdir).waterlogged(),
             Log(species, log_type, axis) => Log(species, log_type, map_axis(axis)),
             Stair(material, facing, flipped) => Stair(material, hdir(facing), flipped),
             WallBanner(facing, color) => WallBanner(hdir(facing), color),
@@ -1287,6 +1456,7 @@ impl Block {
             Button(material, dir) => Button(material, fdir(dir)),
             FenceGate(material, dir, state) => FenceGate(material, hdir(dir), state),
             Smoker(dir) => Smoker(hdir(dir)),
+            Lectern(dir, book) => Lectern(hdir(dir), book),
             Loom(dir) => Loom(hdir(dir)),
             Chest(dir) => Chest(hdir(dir)),
             EnderChest(dir) => EnderChest(hdir(dir)),
@@ -1326,6 +1496,7 @@ impl Block {
             Leaves(Oak, dist) => Leaves(species, dist),
             Trapdoor(Oak, dir, meta) => Trapdoor(species, dir, meta),
             Door(Oak, dir, meta) => Door(species, dir, meta),
+            Waterlogged(_) => self.dry().swap_wood_type(species).waterlogged(),
             _ => self,
         }
     }
@@ -1347,7 +1518,7 @@ impl std::ops::BitOr<Block> for Block {
         if self.solid() {
             self
         } else {
-            rhs
+            rhs.waterlogged_in(self)
         }
     }
 }
@@ -1390,7 +1561,7 @@ pub fn sign_text(text: &str, sign_type: SignType) -> String {
         if i != 0 {
             text.push(',')
         }
-        text.push_str(&format!("'{{\"text\":\"{}\"}}'", line.replace('\'', "\\'")));
+        text.push_str(&version().text(line));
     }
     text.push_str("]}");
     format!("is_waxed:true,front_text:{text},back_text:{text}")
@@ -1,8 +1,6 @@
 //! This is not a good idea, not at all! I could just have a method. But I'm not here
 //! to make sensible engineering choices, I'm here to have fun
 
-use std::mem;
-
 use crate::*;
 
 impl<P: MaybeRef<IVec3>> FnOnce<(P,)> for Level {
@@ -34,8 +32,9 @@ impl FnOnce<(IVec3, Block)> for Level {
 
 impl FnMut<(IVec3, Block)> for Level {
     extern "rust-call" fn call_mut(&mut self, (pos, block): (IVec3, Block)) {
-        let previous = mem::replace(self.block_mut(pos), block);
+        let previous = self.replace_block(pos, block);
         if previous != block {
+            self.block_nbt.remove(&pos);
             self.setblock_recording.push(SetBlock {
                 pos,
                 previous,
@@ -56,8 +55,9 @@ impl FnOnce<(IVec3, Block, String)> for Level {
 
 impl FnMut<(IVec3, Block, String)> for Level {
     extern "rust-call" fn call_mut(&mut self, (pos, block, nbt): (IVec3, Block, String)) {
-        let previous = mem::replace(self.block_mut(pos), block);
+        let previous = self.replace_block(pos, block);
         if previous != block {
+            self.block_nbt.insert(pos, nbt.clone());
             self.setblock_recording.push(SetBlock {
                 pos,
                 previous,
@@ -78,10 +78,10 @@ impl<F: FnOnce(Block) -> Block> FnOnce<(IVec3, F)> for Level {
 
 impl<F: FnOnce(Block) -> Block> FnMut<(IVec3, F)> for Level {
     extern "rust-call" fn call_mut(&mut self, (pos, fun): (IVec3, F)) {
-        let previous = self.block_mut(pos);
-        let block = fun(*previous);
-        let previous = mem::replace(previous, block);
+        let block = fun(self.blocks[pos]);
+        let previous = self.replace_block(pos, block);
         if previous != block {
+            self.block_nbt.remove(&pos);
             self.setblock_recording.push(SetBlock {
                 pos,
                 previous,
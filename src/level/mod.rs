@@ -3,28 +3,26 @@ mod block;
 mod block_map;
 mod column_map;
 mod index_call;
+pub(crate) mod snbt;
+mod synthetic_world;
+mod world_source;
 
-use anvil_region::{
-    position::{RegionChunkPosition, RegionPosition},
-    provider::{FolderRegionProvider, RegionProvider},
-};
 use bevy_ecs::resource::Resource;
 use itertools::Itertools;
-use nbt::CompoundTag;
+use nbt::{CompoundTag, Tag};
 use rayon::prelude::*;
 use std::{
     collections::VecDeque,
-    fs::File,
     ops::{Range, RangeInclusive, Shr},
     path::PathBuf,
 };
-use walkdir::WalkDir;
-use zip::{ZipWriter, write::SimpleFileOptions};
 
-use crate::{ConsItem, DATA_VERSION, HashMap, default, geometry::*};
+use crate::{ConsItem, HashMap, HashSet, Version, default, geometry::*, version};
 pub use biome::*;
 pub use block::*;
 pub use column_map::ColumnMap;
+pub use synthetic_world::SyntheticWorld;
+pub use world_source::{DiskWorld, WorldSource};
 
 use self::block_map::{BlockMap, Section};
 
@@ -33,6 +31,12 @@ use self::block_map::{BlockMap, Section};
 const MIN_SECTION: i32 = -4;
 const MAX_SECTION: i32 = 19;
 const SECTION_COUNT: usize = (MAX_SECTION + 1 - MIN_SECTION) as usize;
+/// Lowest loaded z
+pub const MIN_Z: i32 = MIN_SECTION * 16;
+/// Highest loaded z
+pub const MAX_Z: i32 = MAX_SECTION * 16 + 15;
+/// Biomes are stored in 4×4×4 cells
+const BIOME_CELLS: usize = SECTION_COUNT * 4;
 
 #[derive(Resource)]
 pub struct Level {
@@ -42,34 +46,36 @@ pub struct Level {
     chunk_min: ChunkIndex,
     chunk_max: ChunkIndex,
     blocks: BlockMap<Block>,
-    /// Minecraft stores biomes in 3d, but we only store 2d (taken from height 64)
+    /// Biome at the surface, for the full 3d biomes use [`Level::biome_at`]
     pub biome: ColumnMap<Biome, 4>,
+    /// Biome cells from bottom to top
+    biome_cells: ColumnMap<[Biome; BIOME_CELLS], 4>,
     pub height: ColumnMap<i32>,
     pub water: ColumnMap<Option<i32>>,
+    /// Heightmaps as stored by Minecraft, not kept up to date
+    pub stored_height: ColumnMap<StoredHeight>,
     // This might store a Option<Entity> later
     pub blocked: ColumnMap<ColumnUse>,
     // Pathfinding cost from center (may not be up to date)
     pub reachability: ColumnMap<u32>,
-    dirty_chunks: ColumnMap<bool, 16>,
+    /// Bitmask of modified sections
+    dirty_chunks: ColumnMap<u32, 16>,
+    /// Incremented on changes relevant to pathfinding
+    path_revision: ColumnMap<u32, 16>,
+    /// Block entity data of placed blocks
+    block_nbt: HashMap<IVec3, String>,
     setblock_recording: Vec<SetBlock>,
+    source: Box<dyn WorldSource>,
 }
 
 impl Level {
     // No nice error handling, but we don't really need that for just the three invocations
     pub fn new(read_path: String, write_path: String, area: Rect) -> Self {
-        if read_path != write_path {
-            let read_path = read_path.clone();
-            let write_path = write_path.clone();
-            rayon::spawn(move || {
-                copy_level(read_path, write_path);
-            });
-        }
-        let region_path = {
-            let mut region_path = PathBuf::from(read_path);
-            region_path.push("dimensions/minecraft/overworld/region");
-            region_path.into_os_string().into_string().unwrap()
-        };
-        let chunk_provider = FolderRegionProvider::new(&region_path);
+        Self::from_source(DiskWorld::new(read_path, write_path), area)
+    }
+
+    /// Loads the area (plus a margin) from an arbitrary source, e.g. a [`SyntheticWorld`]
+    pub fn from_source(source: impl WorldSource + 'static, area: Rect) -> Self {
         let load_area = area.grow(crate::LOAD_MARGIN);
         // TODO: use area just as a settlement area but try to load a wider margin around it (need to detect if chunks are present)
         let chunk_min = ChunkIndex::from(area.min - ivec2(crate::LOAD_MARGIN, crate::LOAD_MARGIN));
@@ -77,8 +83,10 @@ impl Level {
 
         let mut blocks = BlockMap::new(load_area, Air);
         let mut biome = ColumnMap::new(load_area);
+        let mut biome_cells = ColumnMap::new_with(load_area, [Biome::default(); BIOME_CELLS]);
         let mut height = ColumnMap::new_with(load_area, MIN_SECTION * 16);
         let mut water = ColumnMap::new(load_area);
+        let mut stored_height = ColumnMap::new(load_area);
 
         // Load chunks. Collecting indexes to vec neccessary for zip
         (chunk_min.1..=chunk_max.1)
@@ -87,110 +95,110 @@ impl Level {
             .par_iter()
             .zip(blocks.sections.par_chunks_exact_mut(SECTION_COUNT))
             .zip(biome.data.par_chunks_exact_mut(4 * 4))
+            .zip(biome_cells.data.par_chunks_exact_mut(4 * 4))
             .zip(height.data.par_chunks_exact_mut(16 * 16))
             .zip(water.data.par_chunks_exact_mut(16 * 16))
-            .for_each(|((((index, sections), biome), heightmap), watermap)| {
-                load_chunk(
-                    &chunk_provider,
-                    (*index).into(),
-                    sections,
-                    biome,
-                    heightmap,
-                    watermap,
-                )
-            });
+            .zip(stored_height.data.par_chunks_exact_mut(16 * 16))
+            .for_each(
+                |((((((index, sections), biome), biome_cells), heightmap), watermap), stored)| {
+                    load_chunk(
+                        &source,
+                        (*index).into(),
+                        sections,
+                        biome,
+                        biome_cells,
+                        heightmap,
+                        watermap,
+                        stored,
+                    )
+                },
+            );
 
         Self {
-            path: PathBuf::from(write_path),
+            path: source.output_path(),
             chunk_min,
             chunk_max,
             blocks,
             biome,
+            biome_cells,
             height,
             water,
+            stored_height,
             blocked: ColumnMap::new(load_area),
             reachability: ColumnMap::new(load_area),
             dirty_chunks: ColumnMap::new(load_area),
+            path_revision: ColumnMap::new(load_area),
+            block_nbt: default(),
             setblock_recording: default(),
+            source: Box::new(source),
         }
     }
 
+    /// Writes the modified sections into the world, keeping all other chunk data.
+    /// Heightmaps and light of modified chunks are recomputed by Minecraft.
+    pub fn save(&self) {
+        for (index, sections) in (self.chunk_min.1..=self.chunk_max.1)
+            .flat_map(|z| (self.chunk_min.0..=self.chunk_max.0).map(move |x| (x, z)))
+            .zip(self.blocks.sections.chunks_exact(SECTION_COUNT))
+        {
+            let dirty = self.dirty_chunks[ChunkIndex::from(index).area().min];
+            if dirty != 0 {
+                merge_chunk(
+                    &*self.source,
+                    index.into(),
+                    sections,
+                    dirty,
+                    &self.block_nbt,
+                )
+            }
+        }
+
+        self.save_metadata();
+    }
+
     /// Saves the world to disk. This is suitable only for debug visualizations:
     /// Some blocks may be changes/information is discarded even though it's not touched,
     /// blockstates ignore neighboring blocks.
     pub fn debug_save(&self) {
-        // Write chunks
-        let mut region_path = self.path.clone();
-        region_path.push("dimensions/minecraft/overworld/region");
-        // Internally, AnvilChunkProvider stores a path. So why require a str??
-        let region_path = region_path.into_os_string().into_string().unwrap();
-        let chunk_provider = FolderRegionProvider::new(&region_path);
-
         // Saving isn't thread safe
         for (index, sections) in (self.chunk_min.1..=self.chunk_max.1)
             .flat_map(|z| (self.chunk_min.0..=self.chunk_max.0).map(move |x| (x, z)))
             .zip(self.blocks.sections.chunks_exact(SECTION_COUNT))
         {
-            if self.dirty_chunks[ChunkIndex::from(index).area().min] {
-                save_chunk(&chunk_provider, index.into(), sections)
+            if self.dirty_chunks[ChunkIndex::from(index).area().min] != 0 {
+                save_chunk(&*self.source, index.into(), sections)
             }
         }
 
         self.save_metadata();
     }
 
-    pub fn save_metadata(&self) {
-        // Edit metadata: Level.dat
-        let nbt_path = self.path.join("level.dat");
-        let mut file = std::fs::File::open(&nbt_path).expect("Failed to open level.dat");
-        let mut nbt =
-            nbt::decode::read_gzip_compound_tag(&mut file).expect("Failed to open level.dat");
-        let data: &mut CompoundTag = nbt.get_mut("Data").expect("Corrupt level.dat");
-
-        let name: &mut String = data.get_mut("LevelName").unwrap();
-        if !name.contains("[replay]") {
-            name.push_str(" [replay]");
-        } else if let Some((start, Ok(count))) = name
-            .rsplit_once(' ')
-            .map(|(start, count)| (start, count.parse::<i32>()))
-        {
-            *name = format!("{start} {}", count + 1);
-        } else {
-            name.push_str(" 2");
-        }
-
-        data.insert(
-            "LastPlayed",
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as i64,
-        );
-
-        data.insert_bool("allowCommands", true);
-
-        data.insert_i8("Difficulty", 0);
-
-        let mut file = std::fs::OpenOptions::new()
-            .write(true)
-            .open(&nbt_path)
-            .expect("Failed to open level.dat");
-        nbt::encode::write_gzip_compound_tag(&mut file, &nbt).expect("Failed to write level.dat");
-
-        // Edit metadata: Game Rules
-        let nbt_path = self.path.join("data/minecraft/game_rules.dat");
-        let mut file = std::fs::File::open(&nbt_path).expect("Failed to open game_rules.dat");
-        let mut nbt =
-            nbt::decode::read_gzip_compound_tag(&mut file).expect("Failed to open level.dat");
-        let data: &mut CompoundTag = nbt.get_mut("data").unwrap();
-
-        data.insert_bool("minecraft:command_block_output", false);
+    /// Blocks that differ from the loaded world, together with their block entity.
+    /// Reads the modified chunks again, so call this before [`Level::save`].
+    pub fn changed_blocks(&self) -> Vec<(IVec3, Block, Option<CompoundTag>)> {
+        (self.chunk_min.1..=self.chunk_max.1)
+            .flat_map(|z| (self.chunk_min.0..=self.chunk_max.0).map(move |x| (x, z)))
+            .zip(self.blocks.sections.chunks_exact(SECTION_COUNT))
+            .collect_vec()
+            .into_par_iter()
+            .flat_map_iter(|(index, sections)| {
+                let dirty = self.dirty_chunks[ChunkIndex::from(index).area().min];
+                if dirty == 0 {
+                    return Vec::new();
+                }
+                changed_in_chunk(
+                    &*self.source,
+                    index.into(),
+                    sections,
+                    dirty,
+                    &self.block_nbt,
+                )
+            })
+            .collect()
+    }
 
-        let mut file = std::fs::OpenOptions::new()
-            .write(true)
-            .open(&nbt_path)
-            .expect("Failed to open game_rules.dat");
-        nbt::encode::write_gzip_compound_tag(&mut file, &nbt).unwrap();
+    pub fn save_metadata(&self) {
+        self.source.save_metadata();
     }
 
     pub fn column_map<T: Clone, const RES: i32>(&self, default: T) -> ColumnMap<T, RES> {
@@ -198,10 +206,24 @@ impl Level {
     }
 
     fn block_mut(&mut self, pos: IVec3) -> &mut Block {
-        self.dirty_chunks[pos] = true;
+        self.dirty_chunks[pos] |= 1 << (pos.z.div_euclid(16) - MIN_SECTION);
         &mut self.blocks[pos]
     }
 
+    /// Returns the previous block
+    fn replace_block(&mut self, pos: IVec3, block: Block) -> Block {
+        let previous = std::mem::replace(self.block_mut(pos), block);
+        if !previous.same_for_pathing(block) {
+            self.path_revision[pos] += 1;
+        }
+        previous
+    }
+
+    /// Changes whenever a block in the chunk changes in a way that matters for pathfinding
+    pub fn path_revision(&self, chunk: ChunkIndex) -> u32 {
+        self.path_revision[chunk.area().min]
+    }
+
     pub fn chunk_min(&self) -> ChunkIndex {
         self.chunk_min
     }
@@ -236,14 +258,20 @@ impl Level {
     pub fn undo_recording(&mut self, cursor: RecordingCursor) -> Vec<SetBlock> {
         let rec = self.setblock_recording.drain(cursor.0..).collect_vec();
         for set in rec.iter().rev() {
-            *self.block_mut(set.pos) = set.previous
+            self.replace_block(set.pos, set.previous);
+            self.block_nbt.remove(&set.pos);
         }
         rec
     }
 
     pub fn apply_recording<'a>(&mut self, rec: impl IntoIterator<Item = &'a SetBlock>) {
         for set in rec.into_iter() {
-            *self.block_mut(set.pos) = set.block
+            self.replace_block(set.pos, set.block);
+            if let Some(nbt) = &set.nbt {
+                self.block_nbt.insert(set.pos, nbt.clone());
+            } else {
+                self.block_nbt.remove(&set.pos);
+            }
         }
     }
 
@@ -292,6 +320,10 @@ impl Level {
     pub fn ground(&self, column: IVec2) -> IVec3 {
         column.extend(self.height[column])
     }
+
+    pub fn biome_at(&self, pos: IVec3) -> Biome {
+        self.biome_cells[pos.truncate()][biome_cell(pos.z)]
+    }
 }
 
 pub trait MaybeRef<T>
@@ -363,31 +395,21 @@ impl<F: FnMut(Block) -> Block> BlockOrFn for F {
     }
 }
 
-// TODO: load stored heightmaps, compare to found heightmaps to detect
-// man-made structures
 fn load_chunk(
-    chunk_provider: &FolderRegionProvider,
+    source: &dyn WorldSource,
     chunk_index: ChunkIndex,
     sections: &mut [Option<Box<Section<Block>>>],
     biomes: &mut [Biome],
+    biome_cells: &mut [[Biome; BIOME_CELLS]],
     heightmap: &mut [i32],
     watermap: &mut [Option<i32>],
+    stored_heightmap: &mut [StoredHeight],
 ) {
-    let region_pos = RegionPosition::from_chunk_position(chunk_index.0, chunk_index.1);
-    let chunk_in_region_pos =
-        RegionChunkPosition::from_chunk_position(chunk_index.0, chunk_index.1);
-    let nbt = chunk_provider
-        .get_region(region_pos)
-        .unwrap()
-        .read_chunk(chunk_in_region_pos)
-        .unwrap();
-    let version = nbt.get_i32("DataVersion").unwrap();
-    if version != DATA_VERSION {
-        eprintln!("Using version {version}; only 1.21.8 is currently tested.",);
-    }
+    let nbt = source.read_chunk(chunk_index);
+    // Chunks that weren't loaded since an update keep their old version
+    let version = Version(nbt.get_i32("DataVersion").unwrap());
 
     // TODO: store CarvingMasks::AIR, seems useful
-    // Also, check out Heightmaps. Maybe we can reuse them or gleam additional information from them
 
     let sections_nbt = nbt.get_compound_tag_vec("sections").unwrap();
 
@@ -399,61 +421,19 @@ fn load_chunk(
             continue;
         }
 
-        // Use a 2d representation of biomes
-        if y_index == 5 {
-            let biome = section_nbt.get_compound_tag("biomes").unwrap();
-            let palette = biome.get_str_vec("palette").unwrap();
-            let palette: Vec<Biome> = palette.iter().map(|n| Biome::from_id(n)).collect();
-            if palette.len() == 1 {
-                for biome in &mut *biomes {
-                    *biome = palette[0];
-                }
-            } else {
-                let bits_per_index = palette.len().next_power_of_two().ilog2();
-                let Ok(indices) = biome.get_i64_vec("data") else {
-                    continue;
-                };
-
-                let mut current_long = 0;
-                let mut current_bit_shift = 0;
-                for biome in &mut *biomes {
-                    let packed = indices[current_long] as u64;
-                    let index = packed.shr(current_bit_shift) as usize % (1 << bits_per_index);
-                    *biome = palette[index];
-
-                    current_bit_shift += bits_per_index;
-                    if current_bit_shift > (64 - bits_per_index) {
-                        current_bit_shift = 0;
-                        current_long += 1;
-                    }
-                }
-            }
-        }
-
-        let block_states = section_nbt.get_compound_tag("block_states").unwrap();
-        let palette = block_states.get_compound_tag_vec("palette").unwrap();
-        let palette: Vec<Block> = palette.iter().map(|nbt| Block::from_nbt(nbt)).collect();
+        decode_biomes(
+            section_nbt.get_compound_tag("biomes").unwrap(),
+            biome_cells,
+            y_index,
+        );
 
         let section =
             sections[(y_index - MIN_SECTION) as usize].insert(Box::new([Air; 16 * 16 * 16]));
-        let Ok(indices) = block_states.get_i64_vec("data") else {
-            continue;
-        };
-        let bits_per_index = bits_per_index(palette.len());
-
-        let mut current_long = 0;
-        let mut current_bit_shift = 0;
-        for i in 0..(16 * 16 * 16) {
-            let packed = indices[current_long] as u64;
-            let index = packed.shr(current_bit_shift) as usize % (1 << bits_per_index);
-            section[i] = palette[index];
-
-            current_bit_shift += bits_per_index;
-            if current_bit_shift > (64 - bits_per_index) {
-                current_bit_shift = 0;
-                current_long += 1;
-            }
-        }
+        decode_block_states(
+            section_nbt.get_compound_tag("block_states").unwrap(),
+            section,
+            version,
+        );
     }
 
     // Build water- & heightmap
@@ -471,7 +451,7 @@ fn load_chunk(
                         } {
                             heightmap[x + z * 16] = height;
                             break 'column;
-                        } else if matches!(block, Water | Ice) {
+                        } else if block.contains_water() | (*block == Ice) {
                             watermap[x + z * 16].get_or_insert(section_index * 16 + y);
                         }
                     }
@@ -479,137 +459,400 @@ fn load_chunk(
             }
         }
     }
+
+    let stored = nbt.get_compound_tag("Heightmaps").ok();
+    let world_surface = stored.and_then(|stored| decode_heightmap(stored, "WORLD_SURFACE"));
+    let motion_blocking_no_leaves =
+        stored.and_then(|stored| decode_heightmap(stored, "MOTION_BLOCKING_NO_LEAVES"));
+    for (i, stored) in stored_heightmap.iter_mut().enumerate() {
+        // Custom-made maps may lack heightmaps
+        *stored = StoredHeight {
+            world_surface: world_surface.as_ref().map_or(heightmap[i], |map| map[i]),
+            motion_blocking_no_leaves: motion_blocking_no_leaves
+                .as_ref()
+                .map_or(heightmap[i], |map| map[i]),
+        };
+    }
+
+    // Derive 2d biomes from the highest surface in each cell
+    for (i, biome) in biomes.iter_mut().enumerate() {
+        let (cell_x, cell_z) = (i % 4 * 4, i / 4 * 4);
+        let surface = (cell_x..cell_x + 4)
+            .cartesian_product(cell_z..cell_z + 4)
+            .map(|(x, z)| {
+                let height = heightmap[x + z * 16];
+                watermap[x + z * 16].map_or(height, |water| water.max(height)) + 1
+            })
+            .max()
+            .unwrap();
+        *biome = biome_cells[i][biome_cell(surface)];
+    }
+}
+
+/// Returns the z of the highest block in each column, in X->Z order
+fn decode_heightmap(heightmaps: &CompoundTag, name: &str) -> Option<Vec<i32>> {
+    let packed = heightmaps.get_i64_vec(name).ok()?;
+    let bits_per_entry = (SECTION_COUNT * 16 + 1).next_power_of_two().ilog2() as usize;
+    let per_long = 64 / bits_per_entry;
+    (0..16 * 16)
+        .map(|i| {
+            let long = *packed.get(i / per_long)? as u64;
+            let value = (long >> (i % per_long * bits_per_entry)) % (1 << bits_per_entry);
+            Some(value as i32 + MIN_SECTION * 16 - 1)
+        })
+        .collect()
+}
+
+fn biome_cell(z: i32) -> usize {
+    (z.div_euclid(4) - MIN_SECTION * 4).clamp(0, BIOME_CELLS as i32 - 1) as usize
+}
+
+/// Biomes are stored in Y->Z->X order, without a minimum of bits per index
+fn decode_biomes(biomes_nbt: &CompoundTag, cells: &mut [[Biome; BIOME_CELLS]], y_index: i32) {
+    let palette = biomes_nbt.get_str_vec("palette").unwrap();
+    let palette: Vec<Biome> = palette.iter().map(|n| Biome::from_id(n)).collect();
+    let min_cell = ((y_index - MIN_SECTION) * 4) as usize;
+    let Ok(indices) = biomes_nbt.get_i64_vec("data") else {
+        for column in cells {
+            column[min_cell..min_cell + 4].fill(palette[0]);
+        }
+        return;
+    };
+    let bits_per_index = palette.len().next_power_of_two().ilog2();
+
+    let mut current_long = 0;
+    let mut current_bit_shift = 0;
+    for i in 0..4 * 4 * 4 {
+        let packed = indices[current_long] as u64;
+        let index = packed.shr(current_bit_shift) as usize % (1 << bits_per_index);
+        cells[i % 16][min_cell + i / 16] = palette[index];
+
+        current_bit_shift += bits_per_index;
+        if current_bit_shift > (64 - bits_per_index) {
+            current_bit_shift = 0;
+            current_long += 1;
+        }
+    }
 }
 
 fn bits_per_index(palette_len: usize) -> usize {
     palette_len.next_power_of_two().ilog2().max(4) as usize
 }
 
+fn decode_block_states(block_states: &CompoundTag, section: &mut Section<Block>, version: Version) {
+    let palette = block_states.get_compound_tag_vec("palette").unwrap();
+    let palette: Vec<Block> = palette
+        .iter()
+        .map(|nbt| Block::from_nbt(nbt, version))
+        .collect();
+
+    let Ok(indices) = block_states.get_i64_vec("data") else {
+        section.fill(palette[0]);
+        return;
+    };
+    let bits_per_index = bits_per_index(palette.len());
+
+    let mut current_long = 0;
+    let mut current_bit_shift = 0;
+    for block in section {
+        let packed = indices[current_long] as u64;
+        let index = packed.shr(current_bit_shift) as usize % (1 << bits_per_index);
+        *block = palette[index];
+
+        current_bit_shift += bits_per_index;
+        if current_bit_shift > (64 - bits_per_index) {
+            current_bit_shift = 0;
+            current_long += 1;
+        }
+    }
+}
+
+fn encode_block_states(section: &Section<Block>) -> CompoundTag {
+    let mut block_states = CompoundTag::new();
+    // Build the palette first (for length)
+    // Minecraft seems to always have Air as id 0 even if there is none
+    let unknown_blocks = UNKNOWN_BLOCKS.read().unwrap();
+    let mut palette = HashMap::default();
+    block_states.insert_compound_tag_vec(
+        "palette",
+        Some(Air).iter().chain(section.iter()).flat_map(|block| {
+            if !palette.contains_key(block) {
+                palette.insert(block, palette.len());
+                Some(block.to_nbt(&unknown_blocks))
+            } else {
+                None
+            }
+        }),
+    );
+
+    let bits_per_index = bits_per_index(palette.len());
+
+    // Reserve minimum required
+    let mut blocks = Vec::with_capacity(4096 / 64 * 4);
+    blocks.push(0);
+    let mut current_long = 0;
+    let mut current_bit_shift = 0;
+
+    for (i, block) in section.iter().enumerate() {
+        blocks[current_long] |= (palette[block] << current_bit_shift) as i64;
+        current_bit_shift += bits_per_index;
+        if current_bit_shift > 64 - bits_per_index {
+            current_bit_shift = 0;
+            current_long += 1;
+            // If there's an unnecessary empty long at the end,
+            // the chunk can't be loaded
+            if (i < 4095) | !64usize.is_multiple_of(bits_per_index) {
+                blocks.push(0);
+            }
+        }
+    }
+    block_states.insert_i64_vec("data", blocks);
+    block_states
+}
+
+/// Position of a block within a section
+fn section_pos(index: ChunkIndex, y_index: i32, i: usize) -> IVec3 {
+    ivec3(index.0 * 16, index.1 * 16, y_index * 16)
+        + ivec3(
+            i as i32 % 16,
+            i as i32 % (16 * 16) / 16,
+            i as i32 / (16 * 16),
+        )
+}
+
 fn save_chunk(
-    chunk_provider: &FolderRegionProvider,
+    source: &dyn WorldSource,
     index: ChunkIndex,
     sections: &[Option<Box<Section<Block>>>],
 ) {
-    chunk_provider
-        .get_region(RegionPosition::from_chunk_position(index.0, index.1))
+    source.write_chunk(index, {
+        let mut nbt = CompoundTag::new();
+        nbt.insert_i32("DataVersion", version().0);
+        nbt.insert_i32("xVec3", index.0);
+        nbt.insert_i32("zVec3", index.1);
+
+        nbt.insert_i64("LastUpdate", 0);
+        nbt.insert_i8("TerrainPopulated", 1);
+        nbt.insert_i64("InhabitetTime", 0);
+        nbt.insert_str("Status", "full");
+
+        // Collect tile entities
+        let mut tile_entities = Vec::new();
+
+        nbt.insert_compound_tag_vec("sections", {
+            sections
+                .iter()
+                .enumerate()
+                .filter_map(|(y_index, section)| {
+                    let y_index = y_index as i32 + MIN_SECTION;
+                    //https://github.com/rust-lang/rust-clippy/issues/8281
+                    #[allow(clippy::question_mark)]
+                    let Some(section) = section else {
+                        return None;
+                    };
+                    let mut nbt = CompoundTag::new();
+                    nbt.insert_i8("Y", y_index as i8);
+                    nbt.insert("block_states", encode_block_states(section));
+
+                    for (i, block) in section.iter().enumerate() {
+                        let pos = section_pos(index, y_index, i);
+                        tile_entities.extend(block.tile_entity_nbt(pos));
+                    }
+
+                    Some(nbt)
+                })
+        });
+
+        nbt.insert_compound_tag_vec("block_entities", tile_entities);
+
+        nbt
+    });
+}
+
+/// Block entity of a placed block, including the data set for it
+fn block_entity(
+    block: Block,
+    pos: IVec3,
+    block_nbt: &HashMap<IVec3, String>,
+) -> Option<CompoundTag> {
+    let mut nbt = block.tile_entity_nbt(pos)?;
+    if let Some(data) = block_nbt.get(&pos).and_then(|data| snbt::parse(data)) {
+        for (key, tag) in data.iter() {
+            nbt.insert(key, tag.clone());
+        }
+    }
+    Some(nbt)
+}
+
+/// Blocks of the modified sections of a chunk that differ from the stored chunk
+fn changed_in_chunk(
+    source: &dyn WorldSource,
+    index: ChunkIndex,
+    sections: &[Option<Box<Section<Block>>>],
+    dirty: u32,
+    block_nbt: &HashMap<IVec3, String>,
+) -> Vec<(IVec3, Block, Option<CompoundTag>)> {
+    let original = source.read_chunk(index);
+    let version = Version(original.get_i32("DataVersion").unwrap());
+    let original_sections: HashMap<i32, &CompoundTag> = original
+        .get_compound_tag_vec("sections")
         .unwrap()
-        .write_chunk(
-            RegionChunkPosition::from_chunk_position(index.0, index.1),
-            {
-                let mut nbt = CompoundTag::new();
-                nbt.insert_i32("DataVersion", DATA_VERSION);
-                nbt.insert_i32("xVec3", index.0);
-                nbt.insert_i32("zVec3", index.1);
-
-                nbt.insert_i64("LastUpdate", 0);
-                nbt.insert_i8("TerrainPopulated", 1);
-                nbt.insert_i64("InhabitetTime", 0);
-                nbt.insert_str("Status", "full");
-
-                // Collect tile entities
-                let mut tile_entities = Vec::new();
-
-                nbt.insert_compound_tag_vec("sections", {
-                    sections
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(y_index, section)| {
-                            let y_index = y_index as i32 + MIN_SECTION;
-                            //https://github.com/rust-lang/rust-clippy/issues/8281
-                            #[allow(clippy::question_mark)]
-                            let Some(section) = section else {
-                                return None;
-                            };
-                            let mut nbt = CompoundTag::new();
-                            nbt.insert_i8("Y", y_index as i8);
-
-                            let mut block_states = CompoundTag::new();
-                            // Build the palette first (for length)
-                            // Minecraft seems to always have Air as id 0 even if there is none
-                            let unknown_blocks = UNKNOWN_BLOCKS.read().unwrap();
-                            let mut palette = HashMap::default();
-                            block_states.insert_compound_tag_vec(
-                                "palette",
-                                Some(Air).iter().chain(section.iter()).flat_map(|block| {
-                                    if !palette.contains_key(block) {
-                                        palette.insert(block, palette.len());
-                                        Some(block.to_nbt(&unknown_blocks))
-                                    } else {
-                                        None
-                                    }
-                                }),
-                            );
-
-                            let bits_per_index = bits_per_index(palette.len());
-
-                            // Reserve minimum required
-                            let mut blocks = Vec::with_capacity(4096 / 64 * 4);
-                            blocks.push(0);
-                            let mut current_long = 0;
-                            let mut current_bit_shift = 0;
-
-                            for (i, block) in section.iter().enumerate() {
-                                blocks[current_long] |=
-                                    (palette[block] << current_bit_shift) as i64;
-                                current_bit_shift += bits_per_index;
-                                if current_bit_shift > 64 - bits_per_index {
-                                    current_bit_shift = 0;
-                                    current_long += 1;
-                                    // If there's an unnecessary empty long at the end,
-                                    // the chunk can't be loaded
-                                    if (i < 4095) | !64usize.is_multiple_of(bits_per_index) {
-                                        blocks.push(0);
-                                    }
-                                }
-
-                                // Collect TileEntity data
-                                {
-                                    let section_base =
-                                        ivec3(index.0 * 16, index.1 * 16, y_index * 16);
-                                    let pos = section_base
-                                        + ivec3(
-                                            i as i32 % 16,
-                                            i as i32 % (16 * 16) / 16,
-                                            i as i32 / (16 * 16),
-                                        );
-                                    tile_entities.extend(block.tile_entity_nbt(pos));
-                                }
-                            }
-                            block_states.insert_i64_vec("data", blocks);
-                            nbt.insert("block_states", block_states);
-
-                            Some(nbt)
-                        })
-                });
-
-                nbt.insert_compound_tag_vec("block_entities", tile_entities);
-
-                nbt
-            },
-        )
-        .unwrap();
+        .into_iter()
+        .map(|section_nbt| (section_nbt.get_i8("Y").unwrap() as i32, section_nbt))
+        .collect();
+
+    let mut changed = Vec::new();
+    for (y_index, section) in (MIN_SECTION..).zip(sections) {
+        let Some(section) = section else {
+            continue;
+        };
+        if dirty & (1 << (y_index - MIN_SECTION)) == 0 {
+            continue;
+        }
+        let mut previous = Box::new([Air; 16 * 16 * 16]);
+        if let Some(block_states) = original_sections
+            .get(&y_index)
+            .and_then(|section_nbt| section_nbt.get_compound_tag("block_states").ok())
+        {
+            decode_block_states(block_states, &mut previous, version);
+        }
+        for (i, (&block, previous)) in section.iter().zip(previous.iter()).enumerate() {
+            let pos = section_pos(index, y_index, i);
+            if (block != *previous) | block_nbt.contains_key(&pos) {
+                changed.push((pos, block, block_entity(block, pos, block_nbt)));
+            }
+        }
+    }
+    changed
 }
 
-fn copy_level(read_path: String, write_path: String) {
-    let _ = std::fs::remove_dir_all(&write_path);
-    copy_dir::copy_dir(read_path, &write_path).expect("Failed to create save");
+/// Replaces the modified sections of a chunk, keeping everything else
+fn merge_chunk(
+    source: &dyn WorldSource,
+    index: ChunkIndex,
+    sections: &[Option<Box<Section<Block>>>],
+    dirty: u32,
+    block_nbt: &HashMap<IVec3, String>,
+) {
+    let original = source.read_chunk(index);
+    let version = Version(original.get_i32("DataVersion").unwrap());
+
+    let mut changed = HashSet::<IVec3>::default();
+    let mut post_processing = match original.get::<&Vec<Tag>>("PostProcessing") {
+        Ok(lists) if lists.len() == SECTION_COUNT => lists
+            .iter()
+            .map(|list| match list {
+                Tag::List(list) => list.clone(),
+                _ => Vec::new(),
+            })
+            .collect_vec(),
+        _ => vec![Vec::new(); SECTION_COUNT],
+    };
+    let mut tile_entities = Vec::new();
+
+    let mut original_sections: HashMap<i32, &CompoundTag> = original
+        .get_compound_tag_vec("sections")
+        .unwrap()
+        .into_iter()
+        .map(|section_nbt| (section_nbt.get_i8("Y").unwrap() as i32, section_nbt))
+        .collect();
+
+    let mut sections_nbt = Vec::new();
+    for y_index in MIN_SECTION..=MAX_SECTION {
+        let section_nbt = original_sections.remove(&y_index);
+        let Some(section) = sections[(y_index - MIN_SECTION) as usize]
+            .as_ref()
+            .filter(|_| dirty & (1 << (y_index - MIN_SECTION)) != 0)
+        else {
+            sections_nbt.extend(section_nbt.cloned());
+            continue;
+        };
 
-    let file = File::create(format!("{write_path}/resources.zip")).unwrap();
-    let mut zip = ZipWriter::new(file);
+        let mut previous = Box::new([Air; 16 * 16 * 16]);
+        if let Some(block_states) =
+            section_nbt.and_then(|section_nbt| section_nbt.get_compound_tag("block_states").ok())
+        {
+            decode_block_states(block_states, &mut previous, version);
+        }
+        let section_post_processing = &mut post_processing[(y_index - MIN_SECTION) as usize];
+        section_post_processing.clear();
+        for (i, (block, previous)) in section.iter().zip(previous.iter()).enumerate() {
+            if block == previous {
+                continue;
+            }
+            let pos = section_pos(index, y_index, i);
+            changed.insert(pos);
+            if block.neighbor_dependent() {
+                // Minecraft updates these shapes when the chunk is first ticked
+                section_post_processing.push(Tag::Short(
+                    ((i % 16) | ((i / 256) << 4) | ((i % 256 / 16) << 8)) as i16,
+                ));
+            }
+            tile_entities.extend(block_entity(*block, pos, block_nbt));
+        }
 
-    for entry in WalkDir::new("resources") {
-        let entry = entry.unwrap();
-        let zip_path = entry.path().strip_prefix("resources").unwrap();
-        if entry.file_type().is_dir() {
-            zip.add_directory_from_path(zip_path, SimpleFileOptions::default())
-                .unwrap();
+        let mut nbt = CompoundTag::new();
+        if let Some(section_nbt) = section_nbt {
+            // Light gets recomputed
+            for (key, tag) in section_nbt.iter() {
+                if !matches!(key.as_str(), "block_states" | "SkyLight" | "BlockLight") {
+                    nbt.insert(key, tag.clone());
+                }
+            }
         } else {
-            zip.start_file_from_path(zip_path, SimpleFileOptions::default())
-                .unwrap();
-            let mut content = File::open(entry.path()).unwrap();
-            std::io::copy(&mut content, &mut zip).unwrap();
+            // The section was empty, borrow the biomes of the one below
+            nbt.insert_i8("Y", y_index as i8);
+            if let Some(biomes) = sections_nbt
+                .last()
+                .and_then(|below| below.get_compound_tag("biomes").ok())
+            {
+                nbt.insert_compound_tag("biomes", biomes.clone());
+            }
+        }
+        nbt.insert("block_states", encode_block_states(section));
+        sections_nbt.push(nbt);
+    }
+    // Sections outside the build height, e.g. for lighting
+    sections_nbt.extend(
+        original_sections
+            .into_iter()
+            .sorted_by_key(|(y_index, _)| *y_index)
+            .map(|(_, section_nbt)| section_nbt.clone()),
+    );
+
+    // Keep block entities that haven't been replaced
+    if let Ok(block_entities) = original.get_compound_tag_vec("block_entities") {
+        for block_entity in block_entities {
+            let pos = ivec3(
+                block_entity.get_i32("x").unwrap(),
+                block_entity.get_i32("z").unwrap(),
+                block_entity.get_i32("y").unwrap(),
+            );
+            if !changed.contains(&pos) {
+                tile_entities.push(block_entity.clone());
+            }
+        }
+    }
+
+    // Heightmaps are recomputed if missing
+    let mut nbt = CompoundTag::new();
+    for (key, tag) in original.iter() {
+        if !matches!(
+            key.as_str(),
+            "sections" | "block_entities" | "Heightmaps" | "PostProcessing"
+        ) {
+            nbt.insert(key, tag.clone());
         }
     }
+    nbt.insert_compound_tag_vec("sections", sections_nbt);
+    nbt.insert_compound_tag_vec("block_entities", tile_entities);
+    nbt.insert(
+        "PostProcessing",
+        Tag::List(post_processing.into_iter().map(Tag::List).collect()),
+    );
+    nbt.insert_i8("isLightOn", 0);
+
+    source.write_chunk(index, nbt);
 }
 
 #[derive(Clone, Debug)]
@@ -623,6 +866,15 @@ pub struct SetBlock {
 #[derive(Default, Copy, Clone)]
 pub struct RecordingCursor(usize);
 
+/// Z of the highest block according to Minecraft's heightmaps
+#[derive(Copy, Clone, Default)]
+pub struct StoredHeight {
+    /// Any non-air block
+    pub world_surface: i32,
+    /// Blocks with collision (and fluids), except leaves
+    pub motion_blocking_no_leaves: i32,
+}
+
 #[derive(Copy, Clone, Default, Eq, PartialEq)]
 pub enum ColumnUse {
     #[default]
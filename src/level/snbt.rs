@@ -0,0 +1,157 @@
+//! Parser for the block entity snbt we generate, so it can be written into chunks directly.
+//! Not a complete implementation (e.g. no heterogeneous lists).
+
+use nbt::{CompoundTag, Tag};
+
+/// Parses the contents of a compound, without the enclosing braces
+pub fn parse(snbt: &str) -> Option<CompoundTag> {
+    let wrapped = format!("{{{snbt}}}");
+    let mut parser = Parser { rest: &wrapped };
+    let nbt = parser.compound()?;
+    parser.rest.trim().is_empty().then_some(nbt)
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.rest = self.rest.trim_start();
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.rest = &self.rest[c.len_utf8()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.eat(c).then_some(())
+    }
+
+    fn compound(&mut self) -> Option<CompoundTag> {
+        self.expect('{')?;
+        let mut nbt = CompoundTag::new();
+        if self.eat('}') {
+            return Some(nbt);
+        }
+        loop {
+            let key = match self.peek()? {
+                '"' | '\'' => self.quoted()?,
+                _ => self.unquoted()?.to_owned(),
+            };
+            self.expect(':')?;
+            nbt.insert(key, self.value()?);
+            if self.eat('}') {
+                return Some(nbt);
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn value(&mut self) -> Option<Tag> {
+        match self.peek()? {
+            '{' => self.compound().map(Tag::Compound),
+            '[' => self.list(),
+            '"' | '\'' => self.quoted().map(Tag::String),
+            _ => self.unquoted().map(literal),
+        }
+    }
+
+    fn list(&mut self) -> Option<Tag> {
+        self.expect('[')?;
+        let array_type = ["B;", "I;", "L;"]
+            .into_iter()
+            .find(|prefix| self.rest.starts_with(prefix));
+        if array_type.is_some() {
+            self.rest = &self.rest[2..];
+        }
+        let mut values = Vec::new();
+        if !self.eat(']') {
+            loop {
+                values.push(self.value()?);
+                if self.eat(']') {
+                    break;
+                }
+                self.expect(',')?;
+            }
+        }
+        let integers = || {
+            values
+                .iter()
+                .map(|tag| match tag {
+                    Tag::Byte(value) => Some(*value as i64),
+                    Tag::Short(value) => Some(*value as i64),
+                    Tag::Int(value) => Some(*value as i64),
+                    Tag::Long(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+        };
+        Some(match array_type {
+            Some("B;") => Tag::ByteArray(integers()?.into_iter().map(|v| v as i8).collect()),
+            Some("I;") => Tag::IntArray(integers()?.into_iter().map(|v| v as i32).collect()),
+            Some(_) => Tag::LongArray(integers()?),
+            None => Tag::List(values),
+        })
+    }
+
+    fn quoted(&mut self) -> Option<String> {
+        let quote = self.peek()?;
+        self.rest = &self.rest[1..];
+        let mut out = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => out.push(match chars.next()?.1 {
+                    'n' => '\n',
+                    't' => '\t',
+                    c => c,
+                }),
+                c if c == quote => {
+                    self.rest = &self.rest[i + 1..];
+                    return Some(out);
+                }
+                c => out.push(c),
+            }
+        }
+        None
+    }
+
+    fn unquoted(&mut self) -> Option<&'a str> {
+        self.peek()?;
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() | matches!(c, '_' | '-' | '.' | '+')))
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        (!word.is_empty()).then_some(word)
+    }
+}
+
+fn literal(word: &str) -> Tag {
+    match word {
+        "true" => return Tag::Byte(1),
+        "false" => return Tag::Byte(0),
+        _ => (),
+    }
+    let (number, suffix) = word.split_at(word.len() - 1);
+    match suffix {
+        "b" | "B" => number.parse().ok().map(Tag::Byte),
+        "s" | "S" => number.parse().ok().map(Tag::Short),
+        "l" | "L" => number.parse().ok().map(Tag::Long),
+        "f" | "F" => number.parse().ok().map(Tag::Float),
+        "d" | "D" => number.parse().ok().map(Tag::Double),
+        _ => word.parse().ok().map(Tag::Int).or_else(|| {
+            word.contains('.')
+                .then(|| word.parse().ok().map(Tag::Double))?
+        }),
+    }
+    .unwrap_or_else(|| Tag::String(word.to_owned()))
+}
@@ -0,0 +1,227 @@
+//! Procedural terrain for running the generator without a Minecraft save,
+//! e.g. in tests and benchmarks.
+
+use std::{path::PathBuf, sync::Mutex};
+
+use nanorand::{RandomGen, WyRand};
+use nbt::CompoundTag;
+
+use super::{MAX_SECTION, MIN_SECTION, block_map::Section, encode_block_states};
+use crate::*;
+
+const WATER_LEVEL: i32 = 62;
+const BASE_HEIGHT: i32 = 69;
+/// Chance for a tree to grow in a column, if the biome has trees
+const TREE_DENSITY: f32 = 0.025;
+
+const BIOMES: &[&str] = &[
+    "minecraft:plains",
+    "minecraft:forest",
+    "minecraft:birch_forest",
+    "minecraft:taiga",
+];
+
+/// Hills, a river, trees and a single biome, generated from a seed.
+/// Chunks are created on first read, written chunks are kept in memory.
+pub struct SyntheticWorld {
+    seed: u64,
+    biome: &'static str,
+    output_path: PathBuf,
+    written: Mutex<HashMap<ChunkIndex, CompoundTag>>,
+}
+
+impl SyntheticWorld {
+    /// The replay datapack and other output is written to `output_path`
+    pub fn new(seed: u64, output_path: impl Into<PathBuf>) -> Self {
+        Self {
+            seed,
+            biome: BIOMES[u64::random(&mut WyRand::new_seed(seed)) as usize % BIOMES.len()],
+            output_path: output_path.into(),
+            written: default(),
+        }
+    }
+
+    /// Smooth noise in -1..1 with features about `scale` blocks wide
+    fn noise(&self, layer: u64, column: IVec2, scale: f32) -> f32 {
+        let pos = column.as_vec2() / scale;
+        let cell = pos.floor();
+        let t = pos - cell;
+        let t = t * t * (3. - 2. * t);
+        let corner = |offset: IVec2| self.hash(layer, cell.as_ivec2() + offset) * 2. - 1.;
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(corner(ivec2(0, 0)), corner(ivec2(1, 0)), t.x),
+            lerp(corner(ivec2(0, 1)), corner(ivec2(1, 1)), t.x),
+            t.y,
+        )
+    }
+
+    /// Random value in 0..1
+    fn hash(&self, layer: u64, column: IVec2) -> f32 {
+        f32::random(&mut WyRand::new_seed(
+            self.seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                ^ (layer << 58)
+                ^ ((column.x as u32 as u64) << 29)
+                ^ column.y as u32 as u64,
+        ))
+    }
+
+    /// Distance from the middle of the river and its half width
+    fn river(&self, column: IVec2) -> (f32, f32) {
+        let offset = (self.hash(2, IVec2::ZERO) - 0.5) * 60.;
+        let center = offset + self.noise(3, ivec2(column.x, 0), 70.) * 40.;
+        let half_width = 2. + self.noise(4, ivec2(column.x, 0), 30.) * 1.5;
+        ((column.y as f32 - center).abs(), half_width)
+    }
+
+    /// Z of the topmost solid block
+    fn height(&self, column: IVec2) -> i32 {
+        let hills = self.noise(0, column, 60.) * 9. + self.noise(1, column, 17.) * 3.;
+        let height = BASE_HEIGHT + hills.round() as i32;
+        let (distance, half_width) = self.river(column);
+        if distance < half_width {
+            WATER_LEVEL - 2 - (distance < half_width / 2.) as i32
+        } else {
+            // Banks slope down towards the river
+            height.min(WATER_LEVEL + ((distance - half_width) * 0.7) as i32)
+        }
+    }
+
+    /// Trunk height of the tree in this column
+    fn tree(&self, column: IVec2) -> Option<i32> {
+        let density = if self.biome == "minecraft:plains" {
+            TREE_DENSITY / 4.
+        } else {
+            TREE_DENSITY
+        };
+        let (distance, half_width) = self.river(column);
+        ((self.hash(5, column) < density)
+            & (distance > half_width + 3.)
+            & (self.height(column) > WATER_LEVEL))
+            .then(|| 4 + (self.hash(6, column) * 3.) as i32)
+    }
+
+    fn generate_chunk(&self, index: ChunkIndex) -> CompoundTag {
+        let area = index.area();
+        let biome = Biome::from_id(self.biome);
+        let species = biome.default_tree_species();
+        let mut sections: Vec<Box<Section<Block>>> = (MIN_SECTION..=MAX_SECTION)
+            .map(|_| Box::new([Air; 16 * 16 * 16]))
+            .collect();
+        let mut set = |pos: IVec3, block: Block| {
+            let local = pos - area.min.extend(MIN_SECTION * 16);
+            let section = &mut sections[(local.z / 16) as usize];
+            let block_ref =
+                &mut section[(local.x + local.y * 16 + local.z % 16 * 16 * 16) as usize];
+            if (*block_ref == Air) | matches!(block, Log(..)) {
+                *block_ref = block;
+            }
+        };
+
+        for column in area {
+            let height = self.height(column);
+            set(column.extend(MIN_SECTION * 16), Bedrock);
+            for z in (height - 3..=height).rev() {
+                set(
+                    column.extend(z),
+                    match (z == height, height < WATER_LEVEL) {
+                        (true, true) => Sand,
+                        (true, false) => Grass,
+                        (false, _) => Dirt,
+                    },
+                );
+            }
+            for z in MIN_SECTION * 16 + 1..height - 3 {
+                set(column.extend(z), Full(Stone));
+            }
+            for z in height + 1..=WATER_LEVEL {
+                set(column.extend(z), Water);
+            }
+        }
+
+        // Trees may reach into this chunk from neighboring ones
+        for column in area.grow(2) {
+            let Some(trunk_height) = self.tree(column) else {
+                continue;
+            };
+            let base = self.height(column) + 1;
+            let top = base + trunk_height - 1;
+            for z in base..=top {
+                if area.contains(column) {
+                    set(column.extend(z), Log(species, LogType::Normal, Axis::Z));
+                }
+            }
+            for z in top - 2..=top + 1 {
+                let radius = if z > top - 1 { 1 } else { 2 };
+                for off in Rect::new_centered(IVec2::ZERO, IVec2::splat(radius * 2 + 1)) {
+                    let leaf = column + off;
+                    if !area.contains(leaf) | (off.abs() == IVec2::splat(2)) {
+                        continue;
+                    }
+                    let distance = off.x.abs() + off.y.abs() + (z - top).max(0);
+                    set(leaf.extend(z), Leaves(species, Some(distance.max(1) as i8)));
+                }
+            }
+        }
+
+        let mut nbt = CompoundTag::new();
+        nbt.insert_i32("DataVersion", DATA_VERSION);
+        nbt.insert_i32("xPos", index.0);
+        nbt.insert_i32("zPos", index.1);
+        nbt.insert_str("Status", "minecraft:full");
+        nbt.insert_compound_tag_vec(
+            "sections",
+            sections.iter().enumerate().map(|(i, section)| {
+                let mut section_nbt = CompoundTag::new();
+                section_nbt.insert_i8("Y", (i as i32 + MIN_SECTION) as i8);
+                section_nbt.insert("block_states", encode_block_states(section));
+                section_nbt.insert("biomes", self.biomes(area));
+                section_nbt
+            }),
+        );
+        nbt.insert_compound_tag_vec("block_entities", Vec::new());
+        nbt
+    }
+
+    /// Cells in the river get the river biome
+    fn biomes(&self, area: Rect) -> CompoundTag {
+        let mut biomes = CompoundTag::new();
+        let mut river_cells = 0i64;
+        for i in 0..16 {
+            let column = area.min + ivec2(i % 4 * 4 + 2, i / 4 * 4 + 2);
+            let (distance, half_width) = self.river(column);
+            if distance < half_width {
+                // The cell is the same on each of the 4 layers
+                for y in 0..4 {
+                    river_cells |= 1i64 << (i + y * 16);
+                }
+            }
+        }
+        if river_cells == 0 {
+            biomes.insert_str_vec("palette", [self.biome]);
+        } else {
+            biomes.insert_str_vec("palette", [self.biome, "minecraft:river"]);
+            biomes.insert_i64_vec("data", vec![river_cells]);
+        }
+        biomes
+    }
+}
+
+impl WorldSource for SyntheticWorld {
+    fn output_path(&self) -> PathBuf {
+        self.output_path.clone()
+    }
+
+    fn read_chunk(&self, index: ChunkIndex) -> CompoundTag {
+        if let Some(nbt) = self.written.lock().unwrap().get(&index) {
+            return nbt.clone();
+        }
+        self.generate_chunk(index)
+    }
+
+    fn write_chunk(&self, index: ChunkIndex, nbt: CompoundTag) {
+        self.written.lock().unwrap().insert(index, nbt);
+    }
+
+    fn save_metadata(&self) {}
+}
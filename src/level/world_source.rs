@@ -0,0 +1,171 @@
+use anvil_region::{
+    position::{RegionChunkPosition, RegionPosition},
+    provider::{FolderRegionProvider, RegionProvider},
+};
+use nbt::CompoundTag;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::ChunkIndex;
+
+/// Where the chunks of a [`Level`](super::Level) are loaded from and saved to
+pub trait WorldSource: Send + Sync {
+    /// Folder the replay datapack and other output is written to
+    fn output_path(&self) -> PathBuf;
+
+    /// Chunk nbt as stored in region files. Panics if the chunk doesn't exist.
+    fn read_chunk(&self, index: ChunkIndex) -> CompoundTag;
+
+    fn write_chunk(&self, index: ChunkIndex, nbt: CompoundTag);
+
+    /// Marks the world as generated and enables commands
+    fn save_metadata(&self);
+}
+
+/// A Minecraft world on disk. Output is written to a copy of it.
+pub struct DiskWorld {
+    read_path: PathBuf,
+    write_path: PathBuf,
+}
+
+impl DiskWorld {
+    /// Starts copying the world in the background if the paths differ
+    pub fn new(read_path: String, write_path: String) -> Self {
+        if read_path != write_path {
+            let read_path = read_path.clone();
+            let write_path = write_path.clone();
+            rayon::spawn(move || {
+                copy_level(read_path, write_path);
+            });
+        }
+        crate::version::init_version(read_path.as_ref());
+        Self {
+            read_path: read_path.into(),
+            write_path: write_path.into(),
+        }
+    }
+}
+
+fn region_path(world_path: &Path) -> String {
+    // Internally, AnvilChunkProvider stores a path. So why require a str??
+    world_path
+        .join("dimensions/minecraft/overworld/region")
+        .into_os_string()
+        .into_string()
+        .unwrap()
+}
+
+impl WorldSource for DiskWorld {
+    fn output_path(&self) -> PathBuf {
+        self.write_path.clone()
+    }
+
+    fn read_chunk(&self, index: ChunkIndex) -> CompoundTag {
+        let region_path = region_path(&self.read_path);
+        FolderRegionProvider::new(&region_path)
+            .get_region(RegionPosition::from_chunk_position(index.0, index.1))
+            .unwrap()
+            .read_chunk(RegionChunkPosition::from_chunk_position(index.0, index.1))
+            .unwrap()
+    }
+
+    fn write_chunk(&self, index: ChunkIndex, nbt: CompoundTag) {
+        let region_path = region_path(&self.write_path);
+        FolderRegionProvider::new(&region_path)
+            .get_region(RegionPosition::from_chunk_position(index.0, index.1))
+            .unwrap()
+            .write_chunk(
+                RegionChunkPosition::from_chunk_position(index.0, index.1),
+                nbt,
+            )
+            .unwrap();
+    }
+
+    fn save_metadata(&self) {
+        // Edit metadata: Level.dat
+        let nbt_path = self.write_path.join("level.dat");
+        let mut file = std::fs::File::open(&nbt_path).expect("Failed to open level.dat");
+        let mut nbt =
+            nbt::decode::read_gzip_compound_tag(&mut file).expect("Failed to open level.dat");
+        let data: &mut CompoundTag = nbt.get_mut("Data").expect("Corrupt level.dat");
+
+        let name: &mut String = data.get_mut("LevelName").unwrap();
+        if !name.contains("[replay]") {
+            name.push_str(" [replay]");
+        } else if let Some((start, Ok(count))) = name
+            .rsplit_once(' ')
+            .map(|(start, count)| (start, count.parse::<i32>()))
+        {
+            *name = format!("{start} {}", count + 1);
+        } else {
+            name.push_str(" 2");
+        }
+
+        data.insert(
+            "LastPlayed",
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+        );
+
+        data.insert_bool("allowCommands", true);
+
+        data.insert_i8("Difficulty", 0);
+
+        // Older versions store game rules in level.dat
+        if let Ok(rules) = data.get_mut::<&mut CompoundTag>("GameRules") {
+            rules.insert_str("commandBlockOutput", "false");
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&nbt_path)
+            .expect("Failed to open level.dat");
+        nbt::encode::write_gzip_compound_tag(&mut file, &nbt).expect("Failed to write level.dat");
+
+        // Edit metadata: Game Rules
+        let nbt_path = self.write_path.join("data/minecraft/game_rules.dat");
+        if !nbt_path.exists() {
+            return;
+        }
+        let mut file = std::fs::File::open(&nbt_path).expect("Failed to open game_rules.dat");
+        let mut nbt =
+            nbt::decode::read_gzip_compound_tag(&mut file).expect("Failed to open level.dat");
+        let data: &mut CompoundTag = nbt.get_mut("data").unwrap();
+
+        data.insert_bool("minecraft:command_block_output", false);
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&nbt_path)
+            .expect("Failed to open game_rules.dat");
+        nbt::encode::write_gzip_compound_tag(&mut file, &nbt).unwrap();
+    }
+}
+
+fn copy_level(read_path: String, write_path: String) {
+    let _ = std::fs::remove_dir_all(&write_path);
+    copy_dir::copy_dir(read_path, &write_path).expect("Failed to create save");
+
+    let file = File::create(format!("{write_path}/resources.zip")).unwrap();
+    let mut zip = ZipWriter::new(file);
+
+    for entry in WalkDir::new("resources") {
+        let entry = entry.unwrap();
+        let zip_path = entry.path().strip_prefix("resources").unwrap();
+        if entry.file_type().is_dir() {
+            zip.add_directory_from_path(zip_path, SimpleFileOptions::default())
+                .unwrap();
+        } else {
+            zip.start_file_from_path(zip_path, SimpleFileOptions::default())
+                .unwrap();
+            let mut content = File::open(entry.path()).unwrap();
+            std::io::copy(&mut content, &mut zip).unwrap();
+        }
+    }
+}
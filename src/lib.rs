@@ -9,12 +9,14 @@
 // Flat module hierarchy is ok for now
 pub mod debug_image;
 pub mod detect_existing_buildings;
+pub mod export;
 mod geometry;
 pub mod goods;
 pub mod house;
 pub mod lang;
 mod level;
 pub mod loot;
+pub mod make_divider;
 pub mod market;
 pub mod names;
 pub mod noise;
@@ -25,13 +27,14 @@ pub mod rand;
 pub mod remove_foliage;
 pub mod replay;
 pub mod roof;
+pub mod route_planner;
 pub mod shipping;
 #[path = "sim/sim.rs"]
 pub mod sim;
+pub mod terraform;
 pub mod test_house;
 pub mod trees;
-// pub mod terraform;
-// pub mod make_divider;
+pub mod version;
 
 use std::cell::Cell;
 
@@ -43,6 +46,7 @@ pub use rand::*;
 use serde::Deserialize;
 pub use sim::*;
 pub use trees::Untree;
+pub use version::{Version, version};
 
 // Replaces SipHash with ahash & disables randomness
 pub type HashMap<K, V> = std::collections::HashMap<K, V, FixedState>;
@@ -62,6 +66,9 @@ pub struct Config {
     // World settings
     pub path: String,
     pub out_path: Option<String>,
+    /// Generate terrain from the seed instead of loading `path`
+    #[serde(default)]
+    pub synthetic_world: bool,
     pub min_x: i32,
     pub max_x: i32,
     pub min_y: i32,
@@ -70,6 +77,9 @@ pub struct Config {
     pub seed: Option<u64>,
     pub villagers: i32,
     pub ticks: i32,
+    /// Changes before this tick are applied instantly instead of being replayed
+    #[serde(default)]
+    pub replay_start_tick: i32,
     // Debug options
     #[serde(default)]
     pub no_building_cost: bool,
@@ -85,6 +95,21 @@ pub struct Config {
     pub show_level_borders: bool,
     #[serde(default)]
     pub export_heightmap: Option<String>,
+    /// Save a map of the settlement every this many ticks into the timelapse folder of the output
+    #[serde(default)]
+    pub timelapse_interval: Option<i32>,
+    /// Write statistics about the economy every this many ticks to metrics.csv in the output
+    #[serde(default)]
+    pub metrics_interval: Option<i32>,
+    /// Write the changed blocks to this path as a Sponge schematic (.schem)
+    #[serde(default)]
+    pub export_schematic: Option<String>,
+    /// Write the changed blocks to this path as a vanilla structure (.nbt)
+    #[serde(default)]
+    pub export_structure: Option<String>,
+    /// Include the villagers in the schematic/structure
+    #[serde(default)]
+    pub export_villagers: bool,
 }
 
 impl Config {
@@ -96,13 +121,15 @@ impl Config {
     }
 
     pub fn load_level(&self) -> Level {
-        Level::new(
-            self.path.clone(),
-            match &self.out_path {
-                Some(out) => out.clone(),
-                None => format!("{} (generated)", self.path.trim_end_matches('/')),
-            },
-            self.area(),
-        )
+        let out_path = match &self.out_path {
+            Some(out) => out.clone(),
+            None => format!("{} (generated)", self.path.trim_end_matches('/')),
+        };
+        if self.synthetic_world {
+            let source = SyntheticWorld::new(self.seed.unwrap_or_default(), out_path);
+            Level::from_source(source, self.area())
+        } else {
+            Level::new(self.path.clone(), out_path, self.area())
+        }
     }
 }
@@ -90,7 +90,10 @@ fn snbt(items: &[(&str, i32, i32)]) -> String {
         if i != 0 {
             out.push(',');
         }
-        out.push_str(&format!("{{Slot:{slot}b,count:{count},id:\"{item}\"}}"));
+        out.push_str(&format!(
+            "{{Slot:{slot}b,{}}}",
+            version().item(item, *count)
+        ));
     }
     out.push(']');
     out
@@ -1,4 +1,5 @@
 use crate::*;
+use sim::{ConsItem, ConsList};
 
 #[derive(Debug, Copy, Clone)]
 pub enum DividerType {
@@ -7,7 +8,39 @@ pub enum DividerType {
     Wall(LineStyle),
 }
 
-pub fn make_divider_single(level: &mut Level, start: Vec2, end: Vec2, kind: DividerType) {
+impl DividerType {
+    /// Local style of enclosure
+    pub fn for_biome(biome: Biome) -> Self {
+        use Biome::*;
+        rand_weighted(match biome {
+            Desert | Mesa | Savanna => &[
+                (1., DividerType::Wall(LineStyle::Thick)),
+                (1., DividerType::Fence(LineStyle::Thick)),
+            ],
+            Snowy | Taiga => &[(1., DividerType::Fence(LineStyle::Thick))],
+            _ => &[
+                (1., DividerType::Fence(LineStyle::Thick)),
+                (0.6, DividerType::Hedge { small: true }),
+                (0.3, DividerType::Hedge { small: false }),
+            ],
+        })
+    }
+}
+
+/// Only replaces air and wild plants
+fn place(level: &mut Level, pos: IVec3, block: Block) {
+    if matches!(level(pos), Air | SmallPlant(..) | SnowLayer) {
+        level(pos, block)
+    }
+}
+
+pub fn make_divider_single(
+    level: &mut Level,
+    start: IVec2,
+    end: IVec2,
+    kind: DividerType,
+) -> ConsList {
+    let cursor = level.recording_cursor();
     match kind {
         DividerType::Hedge { small } => make_hedge(level, start, end, start, small),
         DividerType::Fence(style) => make_fence(
@@ -15,87 +48,107 @@ pub fn make_divider_single(level: &mut Level, start: Vec2, end: Vec2, kind: Divi
             start,
             end,
             start,
-            Wood(level.biome(start).default_tree_species()),
+            Wood(level.biome[start].default_tree_species()),
             style,
         ),
         DividerType::Wall(style) => make_fence(level, start, end, start, Cobble, style),
     }
+    level.pop_recording(cursor).map(ConsItem::Set).collect()
 }
 
 pub fn make_divider(
     level: &mut Level,
-    mut segments: impl Iterator<Item = (Vec2, Vec2)>,
+    mut segments: impl Iterator<Item = (IVec2, IVec2)>,
     kind: DividerType,
-) {
-    let mut make = |(start, end), prev| match kind {
+) -> ConsList {
+    let cursor = level.recording_cursor();
+    let make = |level: &mut Level, (start, end), prev| match kind {
         DividerType::Hedge { small } => make_hedge(level, start, end, prev, small),
         DividerType::Fence(style) => make_fence(
             level,
             start,
             end,
             prev,
-            Wood(level.biome(start).default_tree_species()),
+            Wood(level.biome[start].default_tree_species()),
             style,
         ),
         DividerType::Wall(style) => make_fence(level, start, end, prev, Cobble, style),
     };
     if let Some(mut segment) = segments.next() {
-        make(segment, segment.0);
+        make(level, segment, segment.0);
         for next in segments {
-            make(next, segment.1);
+            make(level, next, segment.1);
             segment = next;
         }
     }
+    level.pop_recording(cursor).map(ConsItem::Set).collect()
+}
+
+/// Opens up the divider at this column, with a gate for fences and walls
+pub fn make_gate(level: &mut Level, column: IVec2, facing: HDir, kind: DividerType) -> ConsList {
+    let cursor = level.recording_cursor();
+    let pos = level.ground(column) + IVec3::Z;
+    for z in 0..3 {
+        if matches!(level(pos + IVec3::Z * z), Leaves(..) | Fence(..)) {
+            level(pos + IVec3::Z * z, Air)
+        }
+    }
+    if !matches!(kind, DividerType::Hedge { .. }) {
+        let species = level.biome[column].default_tree_species();
+        level(pos, FenceGate(Wood(species), facing, Closed));
+    }
+    level.pop_recording(cursor).map(ConsItem::Set).collect()
 }
 
-fn make_hedge(level: &mut Level, start: Vec2, end: Vec2, prev: Vec2, small: bool) {
+fn make_hedge(level: &mut Level, start: IVec2, end: IVec2, prev: IVec2, small: bool) {
     // Maybe have the tree species be a parameter instead for consistency at biome borders?
-    let leaf_block = Block::Leaves(level.biome(start).default_tree_species());
-    let mut prev = prev.at(0);
+    let leaf_block = Leaves(level.biome[start].default_tree_species(), None);
+    let mut prev = prev.extend(level.height[prev] + 1);
     for column in ColumnLineIter::new(start, end, LineStyle::ThickWobbly) {
-        let pos = column.at(level.height(column) + 1);
-        level[pos] |= leaf_block;
-        if prev.1 > pos.1 {
-            level[pos + Vec3(0, 1, 0)] |= leaf_block;
+        let pos = column.extend(level.height[column] + 1);
+        place(level, pos, leaf_block);
+        if prev.z > pos.z {
+            place(level, pos + IVec3::Z, leaf_block);
         }
-        if prev.1 < pos.1 {
-            level[prev + Vec3(0, 1, 0)] |= leaf_block;
+        if prev.z < pos.z {
+            place(level, prev + IVec3::Z, leaf_block);
         }
         if !small {
             if rand(0.8) {
-                level[pos + Vec3(0, 1, 0)] |= leaf_block;
+                place(level, pos + IVec3::Z, leaf_block);
             }
-            if (prev.1 > pos.1) & rand(0.7) {
-                level[pos + Vec3(0, 2, 0)] |= leaf_block;
+            if (prev.z > pos.z) & rand(0.7) {
+                place(level, pos + IVec3::Z * 2, leaf_block);
             }
-            if (prev.1 < pos.1) & rand(0.7) {
-                level[prev + Vec3(0, 2, 0)] |= leaf_block;
+            if (prev.z < pos.z) & rand(0.7) {
+                place(level, prev + IVec3::Z * 2, leaf_block);
             }
-            let mut try_place = |col: Vec2| {
-                let new_pos = col.at(level.height(col) + 1);
-                if (new_pos.1 == pos.1) | (new_pos.1 == pos.1 + 1) {
-                    level[new_pos] |= leaf_block;
+            let try_place = |level: &mut Level, col: IVec2| {
+                let new_pos = col.extend(level.height[col] + 1);
+                if (new_pos.z == pos.z) | (new_pos.z == pos.z + 1) {
+                    place(level, new_pos, leaf_block);
                     true
                 } else {
                     false
                 }
             };
-            if (prev.0 != column.0) & (prev.2 != column.1) {
+            if (prev.x != column.x) & (prev.y != column.y) {
                 let placed = if rand(2.0 / 3.0) {
-                    try_place(Vec2(prev.0, column.1))
+                    try_place(level, ivec2(prev.x, column.y))
                 } else {
                     false
                 };
                 if !placed | rand(0.5) {
-                    try_place(Vec2(column.0, prev.2));
+                    try_place(level, ivec2(column.x, prev.y));
                 }
             } else if (column != start) & (column != end) {
                 try_place(
+                    level,
                     column
                         + if rand(0.5) {
-                            Vec2(rand_1(1.0), 0)
+                            ivec2(rand_1(1.0), 0)
                         } else {
-                            Vec2(0, rand_1(1.0))
+                            ivec2(0, rand_1(1.0))
                         },
                 );
             }
@@ -110,22 +163,22 @@ fn make_hedge(level: &mut Level, start: Vec2, end: Vec2, prev: Vec2, small: bool
 // TODO: random mossyness?
 fn make_fence(
     level: &mut Level,
-    start: Vec2,
-    end: Vec2,
-    prev: Vec2,
-    material: Material,
+    start: IVec2,
+    end: IVec2,
+    prev: IVec2,
+    material: BlockMaterial,
     style: LineStyle,
 ) {
-    let mut prev = prev.at(level.height(prev) + 1);
+    let mut prev = prev.extend(level.height[prev] + 1);
     for column in ColumnLineIter::new(start, end, style) {
-        let pos = column.at(level.height(column) + 1);
-        level[pos] |= Fence(material);
+        let pos = column.extend(level.height[column] + 1);
+        place(level, pos, Fence(material));
         // Bride height variation (don't bother if too steep)
-        if prev.1 == pos.1 + 1 {
-            level[pos + Vec3(0, 1, 0)] |= Fence(material);
+        if prev.z == pos.z + 1 {
+            place(level, pos + IVec3::Z, Fence(material));
         }
-        if prev.1 + 1 == pos.1 {
-            level[prev + Vec3(0, 1, 0)] |= Fence(material);
+        if prev.z + 1 == pos.z {
+            place(level, prev + IVec3::Z, Fence(material));
         }
         prev = pos;
     }
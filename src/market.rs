@@ -7,7 +7,12 @@ use crate::{
     sim::CityCenter,
 };
 
-use self::{construction::ConstructionSite, logistics::MoveTask};
+use self::{
+    construction::{ConstructionSite, Infrastructure},
+    kiln::BrickStock,
+    logistics::MoveTask,
+    make_divider::{DividerType, make_divider_single},
+};
 
 // TODO: Generate villagers visiting stalls
 
@@ -20,39 +25,38 @@ pub struct MarketStall {
 #[derive(Component)]
 pub struct StallNotYetPlanned;
 
-pub fn init_stalls_sys(
-    mut commands: Commands,
-    center: Query<&Pos, With<CityCenter>>,
-) -> Result<()> {
-    let center = center.single()?.block().truncate();
-    for x in -1..=1 {
-        let offset = || ivec2(x * 8 + rand(0..=1), -rand(3..=4));
-        commands.spawn((
-            MarketStall {
-                pos: center + offset(),
-                facing: HDir::YPos,
-            },
-            StallNotYetPlanned,
-        ));
-        commands.spawn((
-            MarketStall {
-                pos: center - offset(),
-                facing: HDir::YNeg,
-            },
-            StallNotYetPlanned,
-        ));
+pub fn init_stalls_sys(mut commands: Commands, centers: Query<&Pos, With<CityCenter>>) {
+    for center in &centers {
+        let center = center.block().truncate();
+        for x in -1..=1 {
+            let offset = || ivec2(x * 8 + rand(0..=1), -rand(3..=4));
+            commands.spawn((
+                MarketStall {
+                    pos: center + offset(),
+                    facing: HDir::YPos,
+                },
+                StallNotYetPlanned,
+            ));
+            commands.spawn((
+                MarketStall {
+                    pos: center - offset(),
+                    facing: HDir::YNeg,
+                },
+                StallNotYetPlanned,
+            ));
+        }
     }
-    Ok(())
 }
 
 pub fn plan_stalls_sys(
     mut commands: Commands,
     mut level: ResMut<Level>,
     houses: Query<(), (With<HousePlan>, Without<Planned>, Without<ConstructionSite>)>,
+    all_stalls: Query<(), With<MarketStall>>,
     possible_stalls: Query<(Entity, &MarketStall), With<StallNotYetPlanned>>,
 ) {
     let houses = houses.iter().count();
-    let stalls = 6 - possible_stalls.iter().count();
+    let stalls = all_stalls.iter().count() - possible_stalls.iter().count();
     let desired_stalls = houses / 3;
     if stalls < desired_stalls {
         let possible = possible_stalls.iter().collect_vec();
@@ -140,18 +144,46 @@ fn replace_wool_colors() -> impl Fn(Color) -> Color {
     move |c| replace[c as usize]
 }
 
+/// Paved part of the town center
+#[derive(Component)]
+pub struct Plaza {
+    area: Rect,
+    bricked: bool,
+}
+
 pub fn upgrade_plaza_sys(
     mut commands: Commands,
     mut level: ResMut<Level>,
     tick: Res<CurrentTick>,
-    center: Query<(Entity, &CityCenter)>,
+    centers: Query<(Entity, &CityCenter)>,
+    mut plazas: Query<&mut Plaza>,
+    bricks: BrickStock,
     mut untree: Untree,
-) -> Result<()> {
-    if tick.0 != 1000 {
-        return Ok(());
+) {
+    if tick.0 == 1000 {
+        for (entity, rect) in &centers {
+            upgrade_plaza(&mut commands, &mut level, &mut untree, entity, **rect);
+        }
+    } else if tick.0 % 1000 == 0 {
+        // Once there are enough bricks, they replace the mud
+        let mut stock = bricks.spare();
+        for mut plaza in &mut plazas {
+            if plaza.bricked | (stock < plaza.area.total() as f32) {
+                continue;
+            }
+            stock -= plaza.area.total() as f32;
+            plaza.bricked = true;
+            let rec = pave_plaza(&mut level, &mut untree, plaza.area, Full(Brick));
+            commands.spawn((
+                Pos(level.ground(plaza.area.center()).as_vec3() + Vec3::Z),
+                ConstructionSite::new(rec),
+                Infrastructure,
+            ));
+        }
     }
-    let (entity, rect) = center.single()?;
+}
 
+fn pave_plaza(level: &mut Level, untree: &mut Untree, rect: Rect, paving: Block) -> ConsList {
     let cursor = level.recording_cursor();
     let mut rec = ConsList::new();
 
@@ -163,14 +195,17 @@ pub fn upgrade_plaza_sys(
         let metr = offset.as_vec2().powf(4.);
         if metr.x + metr.y < (rect.size().max_element() as f32 / 2.).powf(4.) + 0.6 {
             let pos = level.ground(rect.center() + offset);
-            level(pos, PackedMud);
+            // Repaving only covers the old pavement
+            if (paving == PackedMud) | (level(pos) == PackedMud) {
+                level(pos, paving);
+            }
             if rand(0.2) {
                 rec.push_back(ConsItem::Goto(MoveTask {
                     goal: pos + IVec3::Z,
                     distance: 2,
                 }));
             }
-            untree.remove_trees(&mut level, Some(pos.truncate()));
+            untree.remove_trees(level, Some(pos.truncate()));
             level.pop_recording_into(&mut rec, cursor);
         }
         if (offset.x == offset.y)
@@ -181,6 +216,42 @@ pub fn upgrade_plaza_sys(
         }
         offset += dir;
     }
-    commands.entity(entity).insert(ConstructionSite::new(rec));
-    Ok(())
+    rec
+}
+
+fn upgrade_plaza(
+    commands: &mut Commands,
+    level: &mut Level,
+    untree: &mut Untree,
+    entity: Entity,
+    rect: Rect,
+) {
+    let mut rec = pave_plaza(level, untree, rect, PackedMud);
+
+    // Low walls around the plaza, open where streets lead away
+    let kind = DividerType::Wall(LineStyle::Thick);
+    for (start, end) in Polygon(rect.corners().collect()).segments() {
+        let step = (end - start).signum();
+        let mut run = Vec::new();
+        for column in ColumnLineIter::new(start, end, LineStyle::Thick).chain(Some(end)) {
+            let outside = column + IVec2::from(rect.outside_face(column));
+            let walled = (level.blocked[outside] == Free) & level.water[outside].is_none();
+            if walled & (column != end) {
+                run.push(column);
+            } else if let (Some(&first), Some(&last)) = (run.first(), run.last()) {
+                rec.extend(make_divider_single(level, first, last + step, kind));
+                for column in run.drain(..) {
+                    level.blocked[column] = Blocked;
+                }
+            }
+        }
+    }
+
+    commands.entity(entity).insert((
+        Plaza {
+            area: rect,
+            bricked: false,
+        },
+        ConstructionSite::new(rec),
+    ));
 }
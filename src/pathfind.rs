@@ -1,25 +1,71 @@
 use std::{
     cmp::Ordering,
     collections::{BinaryHeap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
 };
 
 use itertools::Itertools;
 
 use crate::*;
 
-const WALK_COST_PER_BLOCK: u32 = 3;
+pub(crate) const WALK_COST_PER_BLOCK: u32 = 3;
 const ROAD_COST_PER_BLOCK: u32 = 2;
 const BOATING_COST_PER_BLOCK: u32 = 2;
 const STAIR_COOLDOWN: i8 = 7;
+/// Taking built stairs is much less of a detour than climbing terrain
+const BUILT_STAIR_COOLDOWN: i8 = 2;
 const BOAT_TOGGLE_COST: u32 = 40 * WALK_COST_PER_BLOCK;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PathSearch {
     pub path: VecDeque<PathingNode>,
     pub success: bool,
     pub cost: u32,
 }
 
+/// Totals over all path searches, for [`crate::metrics`]
+pub static PATH_STATS: PathStats = PathStats {
+    searches: AtomicU64::new(0),
+    failures: AtomicU64::new(0),
+    cost: AtomicU64::new(0),
+    explored: AtomicU64::new(0),
+};
+
+pub struct PathStats {
+    searches: AtomicU64,
+    failures: AtomicU64,
+    cost: AtomicU64,
+    explored: AtomicU64,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PathStatsTotals {
+    pub searches: u64,
+    pub failures: u64,
+    pub cost: u64,
+    /// Number of nodes visited
+    pub explored: u64,
+}
+
+impl PathStats {
+    pub(crate) fn record(&self, search: &PathSearch, explored: usize) {
+        self.searches.fetch_add(1, Relaxed);
+        self.failures.fetch_add(!search.success as u64, Relaxed);
+        self.cost.fetch_add(search.cost as u64, Relaxed);
+        self.explored.fetch_add(explored as u64, Relaxed);
+    }
+
+    /// Returns the totals since the last call
+    pub fn take(&self) -> PathStatsTotals {
+        PathStatsTotals {
+            searches: self.searches.swap(0, Relaxed),
+            failures: self.failures.swap(0, Relaxed),
+            cost: self.cost.swap(0, Relaxed),
+            explored: self.explored.swap(0, Relaxed),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PathingNode {
     pub pos: IVec3,
@@ -54,28 +100,37 @@ impl PartialOrd for Node {
     }
 }
 
-fn heuristic(a: IVec3, b: IVec3) -> i32 {
+pub(crate) fn heuristic(a: IVec3, b: IVec3) -> i32 {
     let horizontal_diff = (a - b).abs();
     (horizontal_diff.x + horizontal_diff.y).max((a.z - b.z).abs())
 }
 
-pub fn pathfind(level: &Level, mut start: IVec3, mut end: IVec3, range_to_end: i32) -> PathSearch {
+pub fn pathfind(level: &Level, start: IVec3, end: IVec3, range_to_end: i32) -> PathSearch {
+    let (search, explored) = pathfind_counted(level, start, end, range_to_end);
+    PATH_STATS.record(&search, explored);
+    search
+}
+
+/// [`pathfind`] without recording it in [`PATH_STATS`], also returns the number of explored nodes
+pub(crate) fn pathfind_counted(
+    level: &Level,
+    mut start: IVec3,
+    mut end: IVec3,
+    range_to_end: i32,
+) -> (PathSearch, usize) {
     if heuristic(start, end) <= range_to_end {
-        return PathSearch {
-            path: default(),
-            success: true,
-            cost: 0,
-        };
+        return (
+            PathSearch {
+                path: default(),
+                success: true,
+                cost: 0,
+            },
+            0,
+        );
     }
     if range_to_end == 0 {
-        for pos in [&mut end, &mut start] {
-            while level(*pos).solid() {
-                *pos += IVec3::Z
-            }
-            while !level(*pos - IVec3::Z).solid() {
-                *pos -= IVec3::Z
-            }
-        }
+        start = snap_to_ground(level, start);
+        end = snap_to_ground(level, end);
     }
     let mut queue = BinaryHeap::new();
     queue.push(Node {
@@ -97,10 +152,24 @@ pub fn pathfind(level: &Level, mut start: IVec3, mut end: IVec3, range_to_end: i
     )
 }
 
-pub fn pathfind_street(level: &Level, start: Rect) -> PathSearch {
+/// Moves the position up out of solid blocks, then down onto the ground
+pub(crate) fn snap_to_ground(level: &Level, mut pos: IVec3) -> IVec3 {
+    while level(pos).solid() {
+        pos += IVec3::Z
+    }
+    while !level(pos - IVec3::Z).solid() {
+        pos -= IVec3::Z
+    }
+    pos
+}
+
+/// Starts only from the side towards `facing`, if given
+pub fn pathfind_street(level: &Level, start: Rect, facing: Option<HDir>) -> PathSearch {
     let mut queue = BinaryHeap::new();
     for column in start.border() {
-        if !start.corners().contains(&column) {
+        if !start.corners().contains(&column)
+            & facing.is_none_or(|facing| start.outside_face(column) == facing)
+        {
             queue.push(Node {
                 pos: level.ground(column) + IVec3::Z,
                 cost: 0,
@@ -110,7 +179,7 @@ pub fn pathfind_street(level: &Level, start: Rect) -> PathSearch {
             });
         }
     }
-    pathfind_with(
+    let (search, explored) = pathfind_with(
         level,
         queue,
         |level, area, path, node, off| {
@@ -127,10 +196,11 @@ pub fn pathfind_street(level: &Level, start: Rect) -> PathSearch {
         },
         |pos, _| (level.blocked[pos] == Street) & (pos.z == level.height[pos] + 1),
         |_| 0,
-    )
+    );
+    PATH_STATS.record(&search, explored);
+    search
 }
 
-// TODO: Make make stairs reduce stair cost
 // TODO: Acknowledge that boats are wider than one block
 fn pathfind_with(
     level: &Level,
@@ -144,7 +214,7 @@ fn pathfind_with(
     ) -> Option<CheckedPos>,
     check_success: impl Fn(IVec3, i32) -> bool,
     heuristic: impl Fn(IVec3) -> i32,
-) -> PathSearch {
+) -> (PathSearch, usize) {
     let mut path = HashMap::<IVec3, (IVec3, bool)>::default();
     for node in &queue {
         path.insert(node.pos, (node.pos, false));
@@ -160,7 +230,7 @@ fn pathfind_with(
                 new_pos,
                 new_cost,
                 boat,
-                stairs_taken,
+                stair_cooldown,
             }) = check_pos(level, area, &mut path, &node, off)
             else {
                 continue;
@@ -178,13 +248,7 @@ fn pathfind_with(
                         } else {
                             WALK_COST_PER_BLOCK
                         },
-                stair_cooldown: if boat {
-                    0
-                } else if stairs_taken {
-                    STAIR_COOLDOWN
-                } else {
-                    (node.stair_cooldown - 1).max(0)
-                },
+                stair_cooldown,
                 in_boat: boat,
             });
 
@@ -208,12 +272,24 @@ fn pathfind_with(
         }
     }
 
+    (
+        PathSearch {
+            path: trace_path(&path, closest_pos),
+            success,
+            cost: closest_cost,
+        },
+        path.len(),
+    )
+}
+
+/// Follows the explored nodes back to the start
+fn trace_path(path: &HashMap<IVec3, (IVec3, bool)>, end: IVec3) -> VecDeque<PathingNode> {
     let mut steps = VecDeque::with_capacity(100);
     steps.push_front(PathingNode {
-        pos: closest_pos,
+        pos: end,
         boat: false,
     });
-    let mut prev = closest_pos;
+    let mut prev = end;
     while let Some((next, boat)) = path.get(&prev) {
         if prev == *next {
             break;
@@ -225,11 +301,112 @@ fn pathfind_with(
         });
         prev = *next;
     }
-    PathSearch {
-        path: steps,
-        success,
-        cost: closest_cost,
+    steps
+}
+
+/// Searches paths to multiple goals without leaving `area`, e.g. a single cluster of the
+/// [`RoutePlanner`](crate::route_planner::RoutePlanner). Also returns the number of explored nodes.
+pub(crate) fn paths_within(
+    level: &Level,
+    area: Rect,
+    start: IVec3,
+    goals: &[IVec3],
+) -> (Vec<Option<PathSearch>>, usize) {
+    let mut path = HashMap::<IVec3, (IVec3, bool)>::default();
+    path.insert(start, (start, false));
+    let mut costs = HashMap::<IVec3, u32>::default();
+    costs.insert(start, 0);
+    let mut remaining = goals.iter().filter(|goal| **goal != start).count();
+    let mut queue = BinaryHeap::new();
+    queue.push(Node {
+        pos: start,
+        cost: 0,
+        cost_with_heuristic: 0,
+        stair_cooldown: 0,
+        in_boat: matches!(level(start - IVec3::Z), Water),
+    });
+    'outer: while let Some(node) = queue.pop() {
+        for off in NEIGHBORS_3D {
+            let Some(CheckedPos {
+                new_pos,
+                new_cost,
+                boat,
+                stair_cooldown,
+            }) = try_pos(level, area, &mut path, &node, off)
+            else {
+                continue;
+            };
+            costs.insert(new_pos, new_cost);
+            queue.push(Node {
+                pos: new_pos,
+                cost: new_cost,
+                cost_with_heuristic: new_cost,
+                stair_cooldown,
+                in_boat: boat,
+            });
+            if goals.contains(&new_pos) {
+                remaining -= 1;
+                if remaining == 0 {
+                    break 'outer;
+                }
+            }
+        }
     }
+    let searches = goals
+        .iter()
+        .map(|goal| {
+            costs.get(goal).map(|&cost| PathSearch {
+                path: trace_path(&path, *goal),
+                success: true,
+                cost,
+            })
+        })
+        .collect();
+    (searches, path.len())
+}
+
+/// Positions where a villager could stand or sit in a boat, near the surface
+pub(crate) fn standing_positions(level: &Level, column: IVec2) -> impl Iterator<Item = IVec3> {
+    let ground = level.height[column];
+    // Buildings can have multiple floors and quarries dig down
+    let min_z = (ground - 16).max(MIN_Z + 1);
+    let max_z = (ground + 24).min(MAX_Z - 1);
+    (min_z..=max_z)
+        .map(move |z| column.extend(z))
+        .filter(|&pos| {
+            let below = level(pos - IVec3::Z);
+            let walk = below.walkable() & !below.no_pathing();
+            (walk | matches!(below, Water))
+                & !level(pos).no_pathing()
+                & !level(pos).solid()
+                & !level(pos + IVec3::Z).solid()
+        })
+}
+
+/// Steps from the given columns in direction `off`, from each position one could stand on:
+/// start, destination and cost
+pub(crate) fn border_crossings(
+    level: &Level,
+    area: Rect,
+    columns: impl IntoIterator<Item = IVec2>,
+    off: IVec3,
+) -> Vec<(IVec3, IVec3, u32)> {
+    let mut crossings = Vec::new();
+    for column in columns {
+        for pos in standing_positions(level, column) {
+            let node = Node {
+                pos,
+                cost: 0,
+                cost_with_heuristic: 0,
+                stair_cooldown: 0,
+                in_boat: matches!(level(pos - IVec3::Z), Water),
+            };
+            if let Some(checked) = try_pos(level, area, &mut default(), &node, off) {
+                crossings.push((pos, checked.new_pos, checked.new_cost));
+            }
+        }
+    }
+    crossings
 }
 
 pub fn reachability_2d_from(level: &Level, start: IVec2) -> ColumnMap<u32> {
@@ -252,7 +429,7 @@ pub fn reachability_2d_from(level: &Level, start: IVec2) -> ColumnMap<u32> {
                 new_pos,
                 new_cost,
                 boat,
-                stairs_taken,
+                stair_cooldown,
             }) = try_pos(level, area, &mut path, &node, off)
             else {
                 continue;
@@ -267,13 +444,7 @@ pub fn reachability_2d_from(level: &Level, start: IVec2) -> ColumnMap<u32> {
                 pos: new_pos,
                 cost: new_cost,
                 cost_with_heuristic: new_cost,
-                stair_cooldown: if boat {
-                    0
-                } else if stairs_taken {
-                    STAIR_COOLDOWN
-                } else {
-                    (node.stair_cooldown - 1).max(0)
-                },
+                stair_cooldown,
                 in_boat: boat,
             });
         }
@@ -300,7 +471,7 @@ pub fn reachability_from(level: &Level, start: IVec3) -> HashMap<IVec3, u32> {
                 new_pos,
                 new_cost,
                 boat,
-                stairs_taken,
+                stair_cooldown,
             }) = try_pos(level, area, &mut path, &node, off)
             else {
                 continue;
@@ -312,13 +483,7 @@ pub fn reachability_from(level: &Level, start: IVec3) -> HashMap<IVec3, u32> {
                 pos: new_pos,
                 cost: new_cost,
                 cost_with_heuristic: new_cost,
-                stair_cooldown: if boat {
-                    0
-                } else if stairs_taken {
-                    STAIR_COOLDOWN
-                } else {
-                    (node.stair_cooldown - 1).max(0)
-                },
+                stair_cooldown,
                 in_boat: boat,
             });
         }
@@ -330,7 +495,8 @@ struct CheckedPos {
     new_pos: IVec3,
     new_cost: u32,
     boat: bool,
-    stairs_taken: bool,
+    /// For the new node
+    stair_cooldown: i8,
 }
 
 fn try_pos(
@@ -348,6 +514,7 @@ fn try_pos(
     // Will we be in a boat in the new node?
     let boat = matches!(level(new_pos - IVec3::Z), Water);
     let mut stairs_taken = false;
+    let mut built_stairs = false;
     if boat {
         if off.z != 0 {
             return None;
@@ -376,12 +543,14 @@ fn try_pos(
                     }
                     new_pos += IVec3::Z;
                     stairs_taken = true;
+                    built_stairs = matches!(level(new_pos - IVec3::Z), Stair(..));
                 } else if !level(new_pos - IVec3::Z).walkable() {
                     if level(node.pos + IVec3::Z).solid() {
                         return None;
                     }
                     new_pos -= IVec3::Z;
                     stairs_taken = true;
+                    built_stairs = matches!(level(node.pos - IVec3::Z), Stair(..));
                 }
             }
         }
@@ -409,7 +578,9 @@ fn try_pos(
         } else {
             WALK_COST_PER_BLOCK
         }
-        + if stairs_taken {
+        + if built_stairs {
+            node.stair_cooldown.min(BUILT_STAIR_COOLDOWN) as u32
+        } else if stairs_taken {
             node.stair_cooldown as u32
         } else {
             0
@@ -424,6 +595,14 @@ fn try_pos(
         new_pos,
         new_cost,
         boat,
-        stairs_taken,
+        stair_cooldown: if boat {
+            0
+        } else if built_stairs {
+            BUILT_STAIR_COOLDOWN
+        } else if stairs_taken {
+            STAIR_COOLDOWN
+        } else {
+            (node.stair_cooldown - 1).max(0)
+        },
     })
 }
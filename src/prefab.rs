@@ -183,11 +183,12 @@ fn load_from_nbt(nbt: &CompoundTag, prefab_name: &str) -> Prefab {
     }
     let origin = markers.get("origin").unwrap().pos;
 
+    let version = Version(nbt.get_i32("DataVersion").unwrap_or(DATA_VERSION));
     let palette: Vec<Block> = nbt
         .get_compound_tag_vec("palette")
         .unwrap()
         .iter()
-        .map(|nbt| Block::from_nbt(nbt))
+        .map(|nbt| Block::from_nbt(nbt, version))
         .collect();
 
     let mut blocks = VecDeque::new();
@@ -1,3 +1,4 @@
+use crate::sim::farm::Farmer;
 use crate::sim::lumberjack::Lumberworker;
 use crate::sim::quarry::Mason;
 use crate::sim::*;
@@ -6,12 +7,15 @@ use bevy_ecs::prelude::*;
 use bevy_ecs::system::SystemChangeTick;
 use flate2::Compression;
 use flate2::write::GzEncoder;
+use fnv::FnvHasher;
+use itertools::Itertools;
 use nbt::encode::write_compound_tag;
 use nbt::{CompoundTag, Tag};
 use serde::Serialize;
 
 use std::fmt::{Display, Write};
 use std::fs::{File, create_dir_all, read_to_string, write};
+use std::hash::{Hash, Hasher};
 use std::io::Write as _;
 use std::ops::DerefMut;
 use std::path::PathBuf;
@@ -20,11 +24,6 @@ use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 
 use self::infinite_sim::Trader;
 
-// TODO: When warping ahead, skip tps except for the last ones
-// to do that, store tps in a seperate list
-// TODO: allow (option when running the generator) to skip the replay of the first n ticks
-// to do that, directly write the commands (skipping tps and setblocks that later get overwritten) to a init function
-
 #[derive(Component, Copy, Clone)]
 pub struct Id(u32);
 
@@ -69,6 +68,23 @@ enum Command {
     Tp(Id, Vec3, Vec3),
 }
 
+impl Hash for Command {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Command::Literal(s) => s.hash(state),
+            Command::Block(pos, block, nbt) => (pos, block, nbt).hash(state),
+            Command::Dust(pos) => pos.hash(state),
+            Command::Tp(id, pos, facing) => {
+                id.0.hash(state);
+                for value in pos.to_array().into_iter().chain(facing.to_array()) {
+                    value.to_bits().hash(state);
+                }
+            }
+        }
+    }
+}
+
 impl Command {
     fn format(self, block_cache: &mut HashMap<Block, String>) -> String {
         match self {
@@ -121,6 +137,39 @@ pub struct Replay {
     total_commands: u64,
     writes_in_flight: Arc<AtomicU32>,
     carry_ids: Vec<(Id, Id)>,
+    /// If set, commands on the main track go into `init` instead of being replayed
+    collapse_into_init: bool,
+    init: Init,
+    /// Of all commands in the order they were issued
+    command_hash: FnvHasher,
+}
+
+/// Commands from before `Config::replay_start_tick`, run all at once when the replay starts.
+/// Only the last setblock per position and the last tp per entity are kept.
+#[derive(Default)]
+struct Init {
+    commands: Vec<Option<Command>>,
+    blocks: HashMap<IVec3, usize>,
+    tps: HashMap<u32, usize>,
+}
+
+impl Init {
+    fn add(&mut self, command: Command) {
+        let overwritten = match &command {
+            Command::Block(pos, ..) => self.blocks.insert(*pos, self.commands.len()),
+            Command::Tp(id, ..) => self.tps.insert(id.0, self.commands.len()),
+            // No point in effects nobody sees
+            Command::Dust(_) => return,
+            Command::Literal(cmd) if cmd.starts_with("playsound") | cmd.starts_with("particle") => {
+                return;
+            }
+            Command::Literal(_) => None,
+        };
+        if let Some(index) = overwritten {
+            self.commands[index] = None;
+        }
+        self.commands.push(Some(command));
+    }
 }
 
 // Commands to be replayed over time.
@@ -132,6 +181,9 @@ struct Track {
     commands_this_tick: Vec<Command>,
     // Stored in reverse order
     commands: Vec<Vec<Command>>,
+    // Teleports are stored seperately so that they can be skipped when warping ahead
+    tps_this_tick: Vec<Command>,
+    tps: Vec<Vec<Command>>,
     command_chunk: i32,
     commands_this_chunk: i32,
 }
@@ -163,6 +215,9 @@ impl Replay {
             total_commands: 0,
             writes_in_flight: default(),
             carry_ids: default(),
+            collapse_into_init: false,
+            init: default(),
+            command_hash: default(),
         };
 
         // Wait for the player to load in
@@ -195,38 +250,59 @@ impl Replay {
     }
 
     pub fn dust(&mut self, pos: IVec3) {
-        self.track().commands_this_tick.push(Command::Dust(pos));
-        self.track().commands_this_chunk += 1;
-        self.total_commands += 1;
+        self.push(Command::Dust(pos));
     }
 
     pub fn block(&mut self, pos: IVec3, block: Block, nbt: Option<String>) {
-        self.track()
-            .commands_this_tick
-            .push(Command::Block(pos, block, nbt));
-        self.track().commands_this_chunk += 1;
-        self.total_commands += 1;
+        self.push(Command::Block(pos, block, nbt));
     }
 
     pub fn tp(&mut self, id: Id, pos: Vec3, facing: Vec3) {
-        self.track()
-            .commands_this_tick
-            .push(Command::Tp(id, pos, facing));
-        self.track().commands_this_chunk += 1;
-        self.total_commands += 1;
+        self.push(Command::Tp(id, pos, facing));
     }
 
     pub fn command(&mut self, msg: String) {
-        self.track().commands_this_tick.push(Command::Literal(msg));
+        self.push(Command::Literal(msg));
+    }
+
+    fn push(&mut self, command: Command) {
+        command.hash(&mut self.command_hash);
+        if self.collapse_into_init & (self.active_track == 0) {
+            self.init.add(command);
+            return;
+        }
+        if let Command::Tp(..) = command {
+            self.track().tps_this_tick.push(command);
+        } else {
+            self.track().commands_this_tick.push(command);
+        }
         self.track().commands_this_chunk += 1;
         self.total_commands += 1;
     }
 
+    /// While enabled, the main track isn't replayed tick by tick.
+    /// Instead its end result gets applied when the replay is started.
+    pub fn set_collapse_into_init(&mut self, collapse: bool) {
+        self.collapse_into_init = collapse;
+    }
+
+    /// Hash of all commands issued so far, for checking determinism
+    pub fn command_hash(&self) -> u64 {
+        self.command_hash.finish()
+    }
+
     fn tick(&mut self) {
         const MAX_COMMANDS_PER_CHUNK: i32 = 40000;
+        // Which tick a command happens in matters too
+        self.command_hash.write_u8(0);
+        if self.collapse_into_init & (self.active_track == 0) {
+            return;
+        }
         if self.track().commands_this_chunk < MAX_COMMANDS_PER_CHUNK {
             let commands = std::mem::take(&mut self.track().commands_this_tick);
             self.track().commands.push(commands);
+            let tps = std::mem::take(&mut self.track().tps_this_tick);
+            self.track().tps.push(tps);
         } else {
             self.flush_chunk();
         }
@@ -234,62 +310,86 @@ impl Replay {
 
     fn flush_chunk(&mut self) {
         // Switch over to the next chunk on the same track
-        // This needs to be the last commands to get executed this tick
+        // This needs to be the last commands to get executed this tick.
+        // Teleports run after the commands, so theirs are only swapped in once they're done.
         let chunk = self.track().command_chunk;
-        self.command(format!(
-            "data modify storage sim_{0}_track{1}:data commands set from storage sim_{0}_track{1}_chunk{2}:data commands",
-            invocation(),
-            self.active_track,
-            chunk + 1
-        ));
+        for (list, target) in [("commands", "commands"), ("tps", "next_tps")] {
+            self.command(format!(
+                "data modify storage sim_{0}_track{1}:data {target} set from storage sim_{0}_track{1}_chunk{2}:data {list}",
+                invocation(),
+                self.active_track,
+                chunk + 1
+            ));
+        }
         let tick_commands = std::mem::take(&mut self.track().commands_this_tick);
         let mut commands = std::mem::replace(&mut self.track().commands, Vec::with_capacity(1000));
         commands.push(tick_commands);
+        let tick_tps = std::mem::take(&mut self.track().tps_this_tick);
+        let mut tps = std::mem::replace(&mut self.track().tps, Vec::with_capacity(1000));
+        tps.push(tick_tps);
+
+        self.write_command_storage(
+            format!(
+                "sim_{}_track{}_chunk{chunk}",
+                invocation(),
+                self.active_track
+            ),
+            vec![("commands", commands), ("tps", tps)],
+        );
+
+        self.track().command_chunk += 1;
+        self.track().commands_this_chunk = 0;
+    }
 
+    /// Writes lists of commands (grouped by tick) to the command storage `{name}:data`
+    fn write_command_storage(&self, name: String, lists: Vec<(&'static str, Vec<Vec<Command>>)>) {
         let data_path = self.level_path.join("data/");
-        let track = self.active_track;
         let arc = self.writes_in_flight.clone();
         arc.fetch_add(1, Ordering::Relaxed);
         rayon::spawn(move || {
             create_dir_all(&data_path).unwrap();
             let mut block_cache = default();
-            let commands_tag = Tag::List(
-                commands
-                    .into_iter()
-                    .rev()
-                    .map(|c| {
-                        nbt::Tag::List(
-                            c.into_iter()
-                                .rev()
-                                .map(|c| {
-                                    let mut nbt = CompoundTag::new();
-                                    nbt.insert("cmd", c.format(&mut block_cache));
-                                    nbt.into()
-                                })
-                                .collect(),
-                        )
-                    })
-                    .collect(),
-            );
+            let mut to_tag = |commands: Vec<Vec<Command>>| {
+                Tag::List(
+                    commands
+                        .into_iter()
+                        .rev()
+                        .map(|c| {
+                            nbt::Tag::List(
+                                c.into_iter()
+                                    .rev()
+                                    .map(|c| {
+                                        let mut nbt = CompoundTag::new();
+                                        if let Command::Tp(id, ..) = c {
+                                            nbt.insert("key", format!("e{:x}", id.0));
+                                        }
+                                        nbt.insert("cmd", c.format(&mut block_cache));
+                                        nbt.into()
+                                    })
+                                    .collect(),
+                            )
+                        })
+                        .collect(),
+                )
+            };
             let mut nbt = CompoundTag::new();
-            nbt.insert("DataVersion", DATA_VERSION);
+            nbt.insert("DataVersion", version().0);
             nbt.insert("data", {
                 let mut nbt = CompoundTag::new();
                 nbt.insert("contents", {
                     let mut nbt = CompoundTag::new();
                     nbt.insert("data", {
                         let mut data = CompoundTag::new();
-                        data.insert("commands", commands_tag);
+                        for (name, commands) in lists {
+                            data.insert(name, to_tag(commands));
+                        }
                         data
                     });
                     nbt
                 });
                 nbt
             });
-            let path = data_path.join(format!(
-                "sim_{}_track{track}_chunk{chunk}/command_storage.dat",
-                invocation()
-            ));
+            let path = data_path.join(format!("{name}/command_storage.dat"));
             std::fs::create_dir_all(path.parent().unwrap()).unwrap();
             let mut file = File::create(path).unwrap();
             // Write to a buffer first.
@@ -303,9 +403,6 @@ impl Replay {
 
             arc.fetch_sub(1, Ordering::Relaxed);
         });
-
-        self.track().command_chunk += 1;
-        self.track().commands_this_chunk = 0;
     }
 
     pub fn switch_to_main_trace(&mut self) {
@@ -321,30 +418,52 @@ impl Replay {
 
     pub fn mcfunction(&self, name: &str, content: &str) {
         let path = self.level_path.join(format!(
-            "datapacks/sim_{0}/data/sim_{0}/function/{name}.mcfunction",
-            invocation()
+            "datapacks/sim_{0}/data/sim_{0}/{1}/{name}.mcfunction",
+            invocation(),
+            version().function_folder()
         ));
         create_dir_all(path.parent().unwrap()).unwrap();
         write(path, content).unwrap();
     }
 
     pub fn finish(mut self) {
+        self.collapse_into_init = false;
         for track in 0..self.tracks.len() {
             self.active_track = track;
             self.flush_chunk();
         }
 
+        let init = std::mem::take(&mut self.init)
+            .commands
+            .into_iter()
+            .flatten()
+            .collect_vec();
+        let has_init = !init.is_empty();
+        if has_init {
+            self.total_commands += init.len() as u64;
+            self.write_command_storage(
+                format!("sim_{}_init", invocation()),
+                vec![("commands", vec![init])],
+            );
+        }
+
         let pack_path = self
             .level_path
             .join(format!("datapacks/sim_{}/", invocation()));
         create_dir_all(&pack_path).unwrap();
         write(
             pack_path.join("pack.mcmeta"),
-            r#"{"pack": {"pack_format": 48, "description": ""}}"#,
+            format!(
+                r#"{{"pack": {{"pack_format": {}, "description": ""}}}}"#,
+                version().pack_format()
+            ),
         )
         .unwrap();
 
-        let tag_path = pack_path.join("data/minecraft/tags/function/");
+        let tag_path = pack_path.join(format!(
+            "data/minecraft/tags/{}/",
+            version().function_folder()
+        ));
         create_dir_all(&tag_path).unwrap();
         write(
             tag_path.join("load.json"),
@@ -370,7 +489,8 @@ impl Replay {
             scoreboard objectives add daytime dummy
             scoreboard objectives add sim_{0}_sleep dummy
             scoreboard objectives add sim_{0}_particle dummy
-            function sim_{0}:play_track_global {{track:0}}
+            scoreboard objectives add sim_{0}_init dummy
+            function sim_{0}:{1}
             scoreboard players set SIM_{0} sim_tick 0
             
             # How many sim ticks to replay per game tick (0 to stop)
@@ -382,11 +502,49 @@ impl Replay {
 
             scoreboard objectives add sim_blurb_cooldown dummy
 
-            gamerule random_tick_speed 0
-            gamerule spawn_mobs false
-            gamerule mob_griefing false
-            gamerule fire_spread_radius_around_player 0
-            gamerule block_drops false
+            {2}
+            ",
+                invocation(),
+                if has_init {
+                    "init_step"
+                } else {
+                    "play_track_global {track:0}"
+                },
+                [
+                    ("random_tick_speed", "0"),
+                    ("spawn_mobs", "false"),
+                    ("mob_griefing", "false"),
+                    ("fire_spread_radius_around_player", "0"),
+                    ("block_drops", "false"),
+                ]
+                .into_iter()
+                .filter_map(|(rule, value)| version().gamerule(rule, value))
+                .join("\n")
+            ),
+        );
+
+        // The init commands are spread over a few game ticks to avoid hitting the command limit.
+        // The main track only starts once they're done.
+        self.mcfunction(
+            "init_step",
+            &format!(
+                "
+            scoreboard players set SIM_{0} sim_{0}_init 5000
+            function sim_{0}:run_init_commands
+            execute if data storage sim_{0}_init:data commands[-1][0] run schedule function sim_{0}:init_step 1t
+            execute unless data storage sim_{0}_init:data commands[-1][0] run function sim_{0}:play_track_global {{track:0}}
+            ",
+                invocation()
+            ),
+        );
+        self.mcfunction(
+            "run_init_commands",
+            &format!(
+                "
+            function sim_{0}:eval with storage sim_{0}_init:data commands[-1][-1]
+            data remove storage sim_{0}_init:data commands[-1][-1]
+            scoreboard players remove SIM_{0} sim_{0}_init 1
+            execute if score SIM_{0} sim_{0}_init matches 1.. if data storage sim_{0}_init:data commands[-1][0] run function sim_{0}:run_init_commands
             ",
                 invocation()
             ),
@@ -415,8 +573,42 @@ impl Replay {
             ", invocation()),
         );
         // Args: track
+        self.mcfunction(
+            "run_current_tps",
+            &format!("
+            $function sim_{0}:handle_tp with storage sim_{0}_track$(track):data tps[-1][-1]
+            $data remove storage sim_{0}_track$(track):data tps[-1][-1]
+            $execute if data storage sim_{0}_track$(track):data tps[-1][0] run function sim_{0}:run_current_tps {{track:$(track)}}
+            ", invocation()),
+        );
+        // While warping, only the last tp of each entity needs to be run
+        // Args: cmd, key
+        self.mcfunction(
+            "handle_tp",
+            &format!("
+            $execute if score SIM_{0} warp matches 2.. run data remove storage sim_{0}:data pending_tps[{{key:\"$(key)\"}}]
+            $execute if score SIM_{0} warp matches 2.. run data modify storage sim_{0}:data pending_tps append value {{cmd:\"$(cmd)\",key:\"$(key)\"}}
+            $execute unless score SIM_{0} warp matches 2.. run $(cmd)
+            ", invocation()),
+        );
+        self.mcfunction(
+            "run_pending_tps",
+            &format!(
+                "
+            function sim_{0}:eval with storage sim_{0}:data pending_tps[-1]
+            data remove storage sim_{0}:data pending_tps[-1]
+            execute if data storage sim_{0}:data pending_tps[0] run function sim_{0}:run_pending_tps
+            ",
+                invocation()
+            ),
+        );
+        // Args: track
         self.mcfunction("tick_track", &format!("
             $function sim_{0}:run_current_commands {{track:$(track)}}
+            $execute if data storage sim_{0}_track$(track):data tps[-1][0] run function sim_{0}:run_current_tps {{track:$(track)}}
+            $data remove storage sim_{0}_track$(track):data tps[-1]
+            $execute if data storage sim_{0}_track$(track):data next_tps run data modify storage sim_{0}_track$(track):data tps set from storage sim_{0}_track$(track):data next_tps
+            $data remove storage sim_{0}_track$(track):data next_tps
             $execute if data storage sim_{0}_track$(track):data commands[0] run data modify storage sim_{0}:data progressable_tracks append value $(track)
             $execute unless data storage sim_{0}_track$(track):data commands[-1][0] run data remove storage sim_{0}_track$(track):data commands[-1]
         ", invocation()));
@@ -452,6 +644,7 @@ impl Replay {
             &format!(
                 "
             $data modify storage sim_{0}_track$(track):data commands set from storage sim_{0}_track$(track)_chunk0:data commands
+            $data modify storage sim_{0}_track$(track):data tps set from storage sim_{0}_track$(track)_chunk0:data tps
             ",
                 invocation()
             ),
@@ -473,7 +666,12 @@ impl Replay {
         // Args: track
         self.mcfunction("tick_track_on_self", &format!("
             $execute if data entity @s data.play run data modify storage sim_{0}_track$(track):data commands set from storage sim_{0}_track$(track)_chunk0:data commands
+            $execute if data entity @s data.play run data modify storage sim_{0}_track$(track):data tps set from storage sim_{0}_track$(track)_chunk0:data tps
             $function sim_{0}:run_current_commands {{track:$(track)}}
+            $execute if data storage sim_{0}_track$(track):data tps[-1][0] run function sim_{0}:run_current_tps {{track:$(track)}}
+            $data remove storage sim_{0}_track$(track):data tps[-1]
+            $execute if data storage sim_{0}_track$(track):data next_tps run data modify storage sim_{0}_track$(track):data tps set from storage sim_{0}_track$(track):data next_tps
+            $data remove storage sim_{0}_track$(track):data next_tps
             $execute unless data storage sim_{0}_track$(track):data commands[-1][0] run data remove storage sim_{0}_track$(track):data commands[-1]
             $execute unless data storage sim_{0}_track$(track):data commands[0] run data remove entity @s data.track
         ", invocation()));
@@ -522,6 +720,7 @@ impl Replay {
         self.mcfunction(
             "sim_tick",
             &format!("
+            execute unless score SIM_{0} warp matches 2.. if data storage sim_{0}:data pending_tps[0] run function sim_{0}:run_pending_tps
             data modify storage sim_{0}:data tracks_to_tick set from storage sim_{0}:data active_tracks
             data modify storage sim_{0}:data progressable_tracks set value []
             function sim_{0}:tick_tracks
@@ -627,6 +826,7 @@ pub fn tick_replay_sys(
     mut moved: Query<(&Id, &Pos, &mut PrevPos, Option<&InBoat>), Changed<Pos>>,
     lumberjacks: Query<&Id, Added<Lumberworker>>,
     masons: Query<&Id, Added<Mason>>,
+    farmers: Query<&Id, Added<Farmer>>,
 ) {
     if replay.skip_changes_once {
         replay.skip_changes_once = false;
@@ -644,7 +844,7 @@ pub fn tick_replay_sys(
     }
     // New villagers
     for (id, pos, vill, is_trader) in &new_vills {
-        let biome = level.biome[pos.block().truncate()];
+        let biome = level.biome_at(pos.block());
         // Display random profession since most aren't used yet
         let profession = rand_weighted(&[
             (5., "none"),
@@ -686,7 +886,8 @@ pub fn tick_replay_sys(
     // Names
     for (id, name) in &named {
         replay.command(format!(
-            "data modify entity {id} CustomName set value \"{name}\"",
+            "data modify entity {id} CustomName set value {}",
+            version().text(name.as_str())
         ));
     }
     // Movement
@@ -705,22 +906,14 @@ pub fn tick_replay_sys(
     }
     // Carrying
     for vill in &changed_vills {
-        if let Some(stack) = vill.carry {
-            replay.command(format!(
-                "data modify entity {} ArmorItems[3] set value {}",
-                vill.carry_id,
-                stack
-                    .good
-                    .display_as_block()
-                    .blockstate(&UNKNOWN_BLOCKS.write().unwrap())
-                    .item_snbt()
-            ));
-        } else {
-            replay.command(format!(
-                "data modify entity {} ArmorItems[3] set value {{}}",
-                vill.carry_id,
-            ));
-        }
+        let item = vill.carry.map(|stack| {
+            stack
+                .good
+                .display_as_block()
+                .blockstate(&UNKNOWN_BLOCKS.write().unwrap())
+                .item_snbt()
+        });
+        replay.command(version().set_head_item(vill.carry_id, item));
     }
     // Professions
     for id in &lumberjacks {
@@ -733,6 +926,11 @@ pub fn tick_replay_sys(
             "data modify entity {id} VillagerDate.profession set value \"mason\"",
         ));
     }
+    for id in &farmers {
+        replay.command(format!(
+            "data modify entity {id} VillagerDate.profession set value \"farmer\"",
+        ));
+    }
 
     replay.tick();
 }
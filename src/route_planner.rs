@@ -0,0 +1,496 @@
+//! Hierarchical pathfinding: The level is divided into chunk-sized clusters, connected by
+//! portals where villagers can cross from one cluster into the next. Paths between the portals
+//! of each cluster are precomputed, so long routes only need a search over the portal graph.
+//! Clusters are rebuilt when blocks relevant to pathfinding change (see [`Level::path_revision`]).
+//! Recently used routes are cached on top of that.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use bevy_ecs::resource::Resource;
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::pathfind::*;
+use crate::*;
+
+/// Routes between clusters closer than this are searched directly
+const MIN_CLUSTER_DISTANCE: i32 = 2;
+const CACHE_SIZE: usize = 256;
+
+#[derive(Resource, Default)]
+pub struct RoutePlanner {
+    clusters: HashMap<ChunkIndex, Cluster>,
+    cache: HashMap<(IVec3, IVec3, i32), CachedRoute>,
+    clock: u64,
+}
+
+#[derive(Default)]
+struct Cluster {
+    /// Revision this was built at
+    revision: Option<u32>,
+    /// Into the +x and +y neighbor
+    exits: [Vec<Crossing>; 2],
+    /// Paths to the other portals of this cluster, by starting portal
+    edges: HashMap<IVec3, Vec<PathSearch>>,
+    /// Steps into neighboring clusters and their cost, by starting portal
+    crossings: HashMap<IVec3, Vec<(IVec3, u32)>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Crossing {
+    from: IVec3,
+    to: IVec3,
+    cost: u32,
+}
+
+struct CachedRoute {
+    search: PathSearch,
+    /// Revisions of the chunks the route passes through
+    revisions: Vec<(ChunkIndex, u32)>,
+    last_used: u64,
+}
+
+/// Either +x or +y
+fn neighbor(chunk: ChunkIndex, dir: usize) -> ChunkIndex {
+    if dir == 0 {
+        ChunkIndex(chunk.0 + 1, chunk.1)
+    } else {
+        ChunkIndex(chunk.0, chunk.1 + 1)
+    }
+}
+
+/// Same as used by [`pathfind`]
+fn search_area(level: &Level) -> Rect {
+    level.area().shrink(2)
+}
+
+/// Whether a route is long enough to be worth searching on the cluster graph
+fn uses_clusters(start: IVec3, end: IVec3) -> bool {
+    let (start, end) = (ChunkIndex::from(start), ChunkIndex::from(end));
+    (start.0 - end.0).abs().max((start.1 - end.1).abs()) >= MIN_CLUSTER_DISTANCE
+}
+
+impl RoutePlanner {
+    /// Like [`pathfind`], but long routes use the cluster graph and successful routes are cached
+    pub fn pathfind(
+        &mut self,
+        level: &Level,
+        start: IVec3,
+        end: IVec3,
+        range_to_end: i32,
+    ) -> PathSearch {
+        self.pathfind_batch(level, &[(start, end, range_to_end)])
+            .pop()
+            .unwrap()
+    }
+
+    /// Resolves multiple searches (start, end & range) in parallel.
+    /// The results are in the same order as the requests.
+    pub fn pathfind_batch(
+        &mut self,
+        level: &Level,
+        requests: &[(IVec3, IVec3, i32)],
+    ) -> Vec<PathSearch> {
+        let mut results = requests
+            .iter()
+            .map(|&key| self.cached(level, key))
+            .collect_vec();
+        let missing = requests
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_none())
+            .map(|(&key, _)| key)
+            .collect_vec();
+        if missing
+            .iter()
+            .any(|&(start, end, _)| uses_clusters(start, end))
+        {
+            self.update(level);
+        }
+        let planner = &*self;
+        let searches = missing
+            .par_iter()
+            .map(|&(start, end, range_to_end)| {
+                planner.search_updated(level, start, end, range_to_end)
+            })
+            .collect::<Vec<_>>();
+
+        // Cache in request order to stay deterministic
+        let mut searches = missing.into_iter().zip(searches);
+        for result in &mut results {
+            if result.is_none() {
+                let (key, search) = searches.next().unwrap();
+                self.remember(level, key, &search);
+                *result = Some(search);
+            }
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    fn cached(&mut self, level: &Level, key: (IVec3, IVec3, i32)) -> Option<PathSearch> {
+        self.clock += 1;
+        let cached = self.cache.get_mut(&key)?;
+        if !cached
+            .revisions
+            .iter()
+            .all(|&(chunk, revision)| level.path_revision(chunk) == revision)
+        {
+            return None;
+        }
+        cached.last_used = self.clock;
+        Some(cached.search.clone())
+    }
+
+    fn remember(&mut self, level: &Level, key: (IVec3, IVec3, i32), search: &PathSearch) {
+        if !search.success {
+            return;
+        }
+        self.clock += 1;
+        let revisions = search
+            .path
+            .iter()
+            .map(|node| ChunkIndex::from(node.pos))
+            .unique()
+            .map(|chunk| (chunk, level.path_revision(chunk)))
+            .collect();
+        self.cache.insert(
+            key,
+            CachedRoute {
+                search: search.clone(),
+                revisions,
+                last_used: self.clock,
+            },
+        );
+        if self.cache.len() > CACHE_SIZE {
+            let oldest = *self
+                .cache
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .unwrap()
+                .0;
+            self.cache.remove(&oldest);
+        }
+    }
+
+    /// Like [`RoutePlanner::pathfind`], but without the cache
+    pub fn search(
+        &mut self,
+        level: &Level,
+        start: IVec3,
+        end: IVec3,
+        range_to_end: i32,
+    ) -> PathSearch {
+        if uses_clusters(start, end) {
+            self.update(level);
+        }
+        self.search_updated(level, start, end, range_to_end)
+    }
+
+    /// Expects the clusters to be up to date
+    fn search_updated(
+        &self,
+        level: &Level,
+        start: IVec3,
+        end: IVec3,
+        range_to_end: i32,
+    ) -> PathSearch {
+        if uses_clusters(start, end)
+            && let Some((search, explored)) = self.route(level, start, end, range_to_end)
+        {
+            PATH_STATS.record(&search, explored);
+            return search;
+        }
+        pathfind(level, start, end, range_to_end)
+    }
+
+    /// Rebuilds the clusters that changed since the last update
+    pub fn update(&mut self, level: &Level) {
+        let area = search_area(level);
+        let in_area = |chunk: ChunkIndex| chunk.area().overlapps(area);
+        let dirty = level
+            .chunks()
+            .filter(|&chunk| {
+                in_area(chunk)
+                    & (self
+                        .clusters
+                        .get(&chunk)
+                        .and_then(|cluster| cluster.revision)
+                        != Some(level.path_revision(chunk)))
+            })
+            .collect_vec();
+        if dirty.is_empty() {
+            return;
+        }
+
+        let borders = dirty
+            .iter()
+            .flat_map(|&chunk| {
+                [
+                    (chunk, 0),
+                    (chunk, 1),
+                    (ChunkIndex(chunk.0 - 1, chunk.1), 0),
+                    (ChunkIndex(chunk.0, chunk.1 - 1), 1),
+                ]
+            })
+            .filter(|&(chunk, dir)| in_area(chunk) & in_area(neighbor(chunk, dir)))
+            .unique()
+            .collect_vec();
+        let exits = borders
+            .par_iter()
+            .map(|&(chunk, dir)| find_exits(level, area, chunk, dir))
+            .collect::<Vec<_>>();
+
+        // Portals only change if a border changed
+        let mut rebuild = dirty.clone();
+        for ((chunk, dir), exits) in borders.into_iter().zip(exits) {
+            let cluster = self.clusters.entry(chunk).or_default();
+            if cluster.exits[dir] != exits {
+                cluster.exits[dir] = exits;
+                rebuild.extend([chunk, neighbor(chunk, dir)]);
+            }
+        }
+        let rebuild = rebuild.into_iter().unique().collect_vec();
+        let built = rebuild
+            .par_iter()
+            .map(|&chunk| self.build_cluster(level, area, chunk))
+            .collect::<Vec<_>>();
+        for (chunk, (edges, crossings)) in rebuild.into_iter().zip(built) {
+            let cluster = self.clusters.entry(chunk).or_default();
+            cluster.edges = edges;
+            cluster.crossings = crossings;
+        }
+
+        for chunk in dirty {
+            self.clusters.entry(chunk).or_default().revision = Some(level.path_revision(chunk));
+        }
+    }
+
+    fn build_cluster(
+        &self,
+        level: &Level,
+        area: Rect,
+        chunk: ChunkIndex,
+    ) -> (
+        HashMap<IVec3, Vec<PathSearch>>,
+        HashMap<IVec3, Vec<(IVec3, u32)>>,
+    ) {
+        let mut crossings = HashMap::<IVec3, Vec<(IVec3, u32)>>::default();
+        for dir in 0..2 {
+            if let Some(cluster) = self.clusters.get(&chunk) {
+                for crossing in &cluster.exits[dir] {
+                    (crossings.entry(crossing.from).or_default())
+                        .push((crossing.to, crossing.cost));
+                }
+            }
+            let previous = if dir == 0 {
+                ChunkIndex(chunk.0 - 1, chunk.1)
+            } else {
+                ChunkIndex(chunk.0, chunk.1 - 1)
+            };
+            if let Some(cluster) = self.clusters.get(&previous) {
+                for crossing in &cluster.exits[dir] {
+                    (crossings.entry(crossing.to).or_default())
+                        .push((crossing.from, crossing.cost));
+                }
+            }
+        }
+
+        let portals = crossings
+            .keys()
+            .copied()
+            .sorted_by_key(|pos| pos.to_array())
+            .collect_vec();
+        let cluster_area = chunk.area().overlap(area);
+        let edges = portals
+            .iter()
+            .map(|&portal| {
+                let (paths, _) = paths_within(level, cluster_area, portal, &portals);
+                let paths = paths
+                    .into_iter()
+                    .flatten()
+                    .filter(|path| path.path.len() > 1)
+                    .collect();
+                (portal, paths)
+            })
+            .collect();
+        (edges, crossings)
+    }
+
+    /// Searches the portal graph, then the final stretch to the goal.
+    /// Also returns the number of explored nodes.
+    fn route(
+        &self,
+        level: &Level,
+        mut start: IVec3,
+        end: IVec3,
+        range_to_end: i32,
+    ) -> Option<(PathSearch, usize)> {
+        if range_to_end == 0 {
+            start = snap_to_ground(level, start);
+        }
+        let start_chunk = ChunkIndex::from(start);
+        let end_chunk = ChunkIndex::from(end);
+        let start_portals = self
+            .clusters
+            .get(&start_chunk)?
+            .crossings
+            .keys()
+            .copied()
+            .sorted_by_key(|pos| pos.to_array())
+            .collect_vec();
+        let (start_paths, mut explored) = paths_within(
+            level,
+            start_chunk.area().overlap(search_area(level)),
+            start,
+            &start_portals,
+        );
+
+        enum Step<'a> {
+            Path(&'a PathSearch),
+            Cross,
+        }
+        // Cost, previous portal and how it was reached
+        let mut best = HashMap::<IVec3, (u32, Option<IVec3>, Step)>::default();
+        let mut queue = BinaryHeap::new();
+        let estimate = |pos: IVec3| heuristic(pos, end) as u32 * WALK_COST_PER_BLOCK;
+        for path in start_paths.iter().flatten() {
+            let portal = path.path.back().unwrap().pos;
+            best.insert(portal, (path.cost, None, Step::Path(path)));
+            queue.push(Reverse((path.cost + estimate(portal), portal.to_array())));
+        }
+
+        let mut closed = HashSet::<IVec3>::default();
+        let mut closest = None::<(i32, [i32; 3])>;
+        let goal = loop {
+            let Some(Reverse((_, pos))) = queue.pop() else {
+                // Unreachable, get as close as possible like `pathfind` does
+                break IVec3::from_array(closest?.1);
+            };
+            let pos = IVec3::from_array(pos);
+            if !closed.insert(pos) {
+                continue;
+            }
+            explored += 1;
+            let distance = (heuristic(pos, end), pos.to_array());
+            if closest.is_none_or(|closest| distance < closest) {
+                closest = Some(distance);
+            }
+            let chunk = ChunkIndex::from(pos);
+            if chunk == end_chunk {
+                break pos;
+            }
+            let cost = best[&pos].0;
+            let Some(cluster) = self.clusters.get(&chunk) else {
+                continue;
+            };
+            let paths = cluster.edges.get(&pos).into_iter().flatten();
+            let crossings = cluster.crossings.get(&pos).into_iter().flatten();
+            for (next, next_cost, step) in paths
+                .map(|path| (path.path.back().unwrap().pos, path.cost, Step::Path(path)))
+                .chain(crossings.map(|&(next, cost)| (next, cost, Step::Cross)))
+            {
+                let next_cost = cost + next_cost;
+                if best.get(&next).is_none_or(|(cost, _, _)| next_cost < *cost) {
+                    best.insert(next, (next_cost, Some(pos), step));
+                    queue.push(Reverse((next_cost + estimate(next), next.to_array())));
+                }
+            }
+        };
+
+        // Collect the steps from the goal back to the start
+        let mut steps = Vec::new();
+        let mut current = goal;
+        loop {
+            let (_, previous, step) = &best[&current];
+            steps.push((current, step));
+            match previous {
+                Some(previous) => current = *previous,
+                None => break,
+            }
+        }
+        let mut path = VecDeque::new();
+        path.push_back(PathingNode {
+            pos: start,
+            boat: false,
+        });
+        for (pos, step) in steps.into_iter().rev() {
+            match step {
+                Step::Path(search) => path.extend(search.path.iter().skip(1).copied()),
+                Step::Cross => path.push_back(PathingNode {
+                    pos,
+                    boat: matches!(level(pos - IVec3::Z), Water),
+                }),
+            }
+        }
+
+        let (last_stretch, last_explored) = pathfind_counted(level, goal, end, range_to_end);
+        path.extend(last_stretch.path.iter().skip(1).copied());
+        Some((
+            PathSearch {
+                path,
+                success: last_stretch.success,
+                cost: best[&goal].0 + last_stretch.cost,
+            },
+            explored + last_explored,
+        ))
+    }
+}
+
+/// Crossings from the cluster into its neighbor in direction `dir`. Only crossings that work
+/// both ways are used, so the portal graph can be undirected. Each group of adjacent crossings
+/// forms one entrance, of which only the middle one is kept.
+fn find_exits(level: &Level, area: Rect, chunk: ChunkIndex, dir: usize) -> Vec<Crossing> {
+    let rect = chunk.area().overlap(area);
+    let (columns, off, along): (Vec<IVec2>, _, fn(IVec3) -> i32) = if dir == 0 {
+        (
+            (rect.min.y..=rect.max.y)
+                .map(|y| ivec2(rect.max.x, y))
+                .collect(),
+            IVec3::X,
+            |pos| pos.y,
+        )
+    } else {
+        (
+            (rect.min.x..=rect.max.x)
+                .map(|x| ivec2(x, rect.max.y))
+                .collect(),
+            IVec3::Y,
+            |pos| pos.x,
+        )
+    };
+
+    let back = border_crossings(
+        level,
+        area,
+        columns.iter().map(|column| column + off.truncate()),
+        -off,
+    )
+    .into_iter()
+    .map(|(from, to, cost)| ((to, from), cost))
+    .collect::<HashMap<_, _>>();
+    let mut entrances: Vec<Vec<Crossing>> = Vec::new();
+    for (from, to, cost) in border_crossings(level, area, columns, off) {
+        let Some(back_cost) = back.get(&(from, to)) else {
+            continue;
+        };
+        let cost = cost.max(*back_cost);
+        let crossing = Crossing { from, to, cost };
+        let adjacent = entrances.iter_mut().rev().find(|entrance| {
+            let last = entrance.last().unwrap();
+            (along(last.from) == along(from) - 1)
+                & ((last.from.z - from.z).abs() <= 1)
+                & ((last.to.z - to.z).abs() <= 1)
+        });
+        match adjacent {
+            Some(entrance) => entrance.push(crossing),
+            None => entrances.push(vec![crossing]),
+        }
+    }
+    entrances
+        .into_iter()
+        .map(|entrance| entrance[entrance.len() / 2])
+        .collect()
+}
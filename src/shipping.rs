@@ -4,30 +4,38 @@ use num_traits::FromPrimitive;
 
 use crate::*;
 
-pub fn identify_water_courses(mut level: ResMut<Level>) {
-    let mut rivers = Vec::new();
-    let mut claimed = level.column_map::<bool, 1>(false);
-    let mut color = 0;
+/// Labels each water column with the index of the connected body of water it belongs to
+pub fn water_courses(level: &Level) -> ColumnMap<Option<u32>> {
+    let mut courses = level.column_map::<Option<u32>, 1>(None);
+    let mut count = 0;
     for column in level.area() {
-        if claimed[column] {
+        if courses[column].is_some() | level.water[column].is_none() {
             continue;
         }
-        if level.water[column].is_some() {
-            color = (color + 1) % 16;
-            let mut river = HashSet::default();
-            let mut to_check = VecDeque::from(vec![column]);
-            while let Some(column) = to_check.pop_front() {
-                for off in NEIGHBORS_2D {
-                    let next = column + off;
-                    if level.area().contains(next) && !claimed[next] & level.water[next].is_some() {
-                        river.insert(next);
-                        claimed[next] = true;
-                        to_check.push_back(next)
-                    }
+        courses[column] = Some(count);
+        let mut to_check = VecDeque::from(vec![column]);
+        while let Some(column) = to_check.pop_front() {
+            for off in NEIGHBORS_2D {
+                let next = column + off;
+                if level.area().contains(next)
+                    && courses[next].is_none() & level.water[next].is_some()
+                {
+                    courses[next] = Some(count);
+                    to_check.push_back(next)
                 }
-                level(column.extend(80), Wool(Color::from_i32(color).unwrap()));
             }
-            rivers.push(river);
+        }
+        count += 1;
+    }
+    courses
+}
+
+pub fn identify_water_courses(mut level: ResMut<Level>) {
+    let courses = water_courses(&level);
+    for column in level.area() {
+        if let Some(course) = courses[column] {
+            let color = Color::from_u32((course + 1) % 16).unwrap();
+            level(column.extend(80), Wool(color));
         }
     }
 }
@@ -0,0 +1,170 @@
+//! Bridges where roads cross narrow rivers, instead of making travelers switch to boats
+
+use super::*;
+use crate::pathfind::PathingNode;
+
+/// Longer crossings are left to boats
+const MAX_SPAN: i32 = 16;
+const MAX_WOODEN_SPAN: i32 = 8;
+const SUPPORT_SPACING: i32 = 4;
+
+/// Its walkway becomes street once built
+#[derive(Component)]
+pub struct Bridge {
+    walkway: Vec<IVec2>,
+}
+
+/// Replaces the boat crossings of a road by bridges where possible
+pub fn plan_bridges(
+    commands: &mut Commands,
+    level: &mut Level,
+    courses: &ColumnMap<Option<u32>>,
+    road: &mut VecDeque<PathingNode>,
+) {
+    let mut bridged = VecDeque::new();
+    let mut i = 0;
+    while i < road.len() {
+        if !road[i].boat {
+            bridged.push_back(road[i]);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while (i < road.len()) && road[i].boat {
+            i += 1;
+        }
+        // Roads can start or end on the water, then there's no bank to build from
+        let bridge = if (start > 0) & (i < road.len()) {
+            make_bridge(level, courses, road[start - 1].pos, road[i].pos)
+        } else {
+            None
+        };
+        let Some((cons, walkway, nodes)) = bridge else {
+            bridged.extend(road.range(start..i).copied());
+            continue;
+        };
+        // Replace the bank nodes too, as the deck might be a step higher
+        bridged.pop_back();
+        bridged.extend(nodes);
+        i += 1;
+        commands.spawn((
+            Pos(road[start - 1].pos.as_vec3()),
+            Bridge { walkway },
+            ConstructionSite::new(cons),
+            Infrastructure,
+        ));
+    }
+    *road = bridged;
+}
+
+/// Straight bridge from bank to bank. Returns the blocks to place,
+/// the walkway columns and the path along its center.
+fn make_bridge(
+    level: &mut Level,
+    courses: &ColumnMap<Option<u32>>,
+    from: IVec3,
+    to: IVec3,
+) -> Option<(ConsList, Vec<IVec2>, Vec<PathingNode>)> {
+    let diff = (to - from).truncate();
+    let (main, side) = if diff.x.abs() >= diff.y.abs() {
+        (ivec2(diff.x.signum(), 0), IVec2::Y)
+    } else {
+        (ivec2(0, diff.y.signum()), IVec2::X)
+    };
+    let length = diff.dot(main);
+    // Avoid very crooked bridges
+    if (length > MAX_SPAN) | (diff.dot(side).abs() > (length / 4).max(2)) {
+        return None;
+    }
+    let center = |t: i32| {
+        (from.truncate().as_vec2() + diff.as_vec2() * t as f32 / length as f32)
+            .round()
+            .as_ivec2()
+    };
+
+    // Only cross one river at a time
+    let mut course = None;
+    let mut water_z = i32::MIN;
+    for t in 0..=length {
+        let column = center(t);
+        let Some(water) = level.water[column] else {
+            continue;
+        };
+        if course.is_some_and(|course| Some(course) != courses[column]) {
+            return None;
+        }
+        course = courses[column];
+        water_z = water_z.max(water);
+    }
+    course?;
+
+    let deck_z = (from.z - 1).max(to.z - 1).max(water_z + 1);
+    if (deck_z - from.z >= 1) | (deck_z - to.z >= 1) {
+        return None;
+    }
+    for t in 0..=length {
+        for offset in -2..=2 {
+            let column = center(t) + side * offset;
+            if (level.blocked[column] == Blocked)
+                | level(column.extend(deck_z + 1)).solid()
+                | level(column.extend(deck_z + 2)).solid()
+            {
+                return None;
+            }
+        }
+    }
+
+    let species = level.biome[from].random_tree_species();
+    let (deck, railing, support) = if length <= MAX_WOODEN_SPAN {
+        (
+            Full(Wood(species)),
+            Fence(Wood(species)),
+            Log(species, LogType::Normal, Axis::Z),
+        )
+    } else {
+        (Full(StoneBrick), Fence(StoneBrick), Full(Cobble))
+    };
+
+    let cursor = level.recording_cursor();
+    for t in (SUPPORT_SPACING..length).step_by(SUPPORT_SPACING as usize) {
+        for offset in [-2, 2] {
+            let column = center(t) + side * offset;
+            level.fill_at(Some(column), level.height[column] + 1..deck_z, support);
+        }
+    }
+    let mut walkway = Vec::new();
+    for t in 0..=length {
+        for offset in -2..=2 {
+            let column = center(t) + side * offset;
+            level(column.extend(deck_z), deck);
+            // Not street until it's finished
+            level.blocked[column] = Blocked;
+            let above = column.extend(deck_z + 1);
+            if (offset.abs() == 2) & (t > 0) & (t < length) {
+                level(above, railing);
+            } else {
+                if level(above) != Air {
+                    level(above, Air);
+                }
+                walkway.push(column);
+            }
+        }
+    }
+    let cons = level.pop_recording(cursor).map(ConsItem::Set).collect();
+
+    let nodes = (0..=length)
+        .map(|t| PathingNode {
+            pos: center(t).extend(deck_z + 1),
+            boat: false,
+        })
+        .collect();
+    Some((cons, walkway, nodes))
+}
+
+pub fn finish_bridges_sys(mut level: ResMut<Level>, bridges: Query<&Bridge, Added<Built>>) {
+    for bridge in &bridges {
+        for &column in &bridge.walkway {
+            level.blocked[column] = Street;
+        }
+    }
+}
@@ -5,6 +5,11 @@ use sim::*;
 
 use self::{
     desire_lines::{add_desire_line, DesireLines},
+    farm::Field,
+    kiln::{BrickKiln, BrickStock},
+    lots::{Lots, TOWN_LOT_REACH},
+    make_divider::{DividerType, make_divider, make_gate},
+    names::tavern_name,
     pathfind::pathfind_street,
     quarry::Quarry,
 };
@@ -14,7 +19,9 @@ pub struct Planned(pub Vec<IVec2>);
 
 #[derive(Component)]
 pub struct HousePlan {
-    area: Rect,
+    pub area: Rect,
+    /// Towards the road, if it's on a lot
+    pub facing: Option<HDir>,
 }
 
 #[derive(Component)]
@@ -23,7 +30,9 @@ pub struct House {
 }
 
 #[derive(Component)]
-pub struct Tavern;
+pub struct Tavern {
+    pub name: String,
+}
 
 #[derive(Component)]
 pub struct ToBeBuild;
@@ -43,14 +52,37 @@ pub fn wateryness(level: &Level, area: Rect) -> f32 {
         / area.total() as f32
 }
 
-pub fn choose_starting_area(level: &Level) -> Rect {
+/// Build area needed per town center
+const AREA_PER_TOWN: i32 = 400 * 400;
+const MAX_TOWNS: i32 = 4;
+/// Minimum distance between town centers
+const TOWN_SPACING: f32 = 200.;
+
+/// Large maps get several towns, each with their own center
+pub fn choose_starting_areas(level: &Level) -> Vec<Rect> {
+    let count = (level.area().total() / AREA_PER_TOWN).clamp(1, MAX_TOWNS);
+    let mut areas: Vec<Rect> = Vec::new();
+    for _ in 0..count {
+        let Some(area) = choose_starting_area(level, &areas) else {
+            break;
+        };
+        areas.push(area);
+    }
+    areas
+}
+
+fn choose_starting_area(level: &Level, others: &[Rect]) -> Option<Rect> {
     optimize(
         Rect::new_centered(level.area().center(), IVec2::splat(44)),
         |area, temperature| {
             let max_move = (300. * temperature) as i32;
             *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
 
-            if !level.area().has_subrect(*area) {
+            if !level.area().has_subrect(*area)
+                | others
+                    .iter()
+                    .any(|other| other.center_vec2().distance(area.center_vec2()) < TOWN_SPACING)
+            {
                 return f32::INFINITY;
             }
             // TODO: try place near river
@@ -63,25 +95,46 @@ pub fn choose_starting_area(level: &Level) -> Rect {
             wateryness(level, *area) * 20. + unevenness(level, *area) + distance.powf(2.) / 2.
         },
         300,
-        1,
+        if others.is_empty() { 1 } else { 5 },
     )
-    .unwrap()
-    .shrink(10)
+    .map(|area| area.shrink(10))
 }
 
 pub fn plan_house_sys(
     mut commands: Commands,
     level: Res<Level>,
+    mut lots: ResMut<Lots>,
     planned: Query<(), (With<HousePlan>, With<Planned>)>,
-    center: Query<&Pos, With<CityCenter>>,
+    villagers: Query<&Town>,
+    centers: Query<(&Pos, &Reachability), With<CityCenter>>,
 ) -> Result<()> {
     if planned.iter().len() > 0 {
         return Ok(());
     }
 
-    // TODO: On a large map, allow for multiple town centers
-    let center = center.single()?.truncate();
-    let Some(area) = optimize(
+    // Towns grow according to their population
+    let Some(town) = villagers.iter().collect_vec().try_choose().copied() else {
+        return Ok(());
+    };
+    let (center, reachability) = centers.get(town.0)?;
+    let center = center.truncate();
+    let cost = |area: Rect| {
+        if !level.free(area) {
+            return f32::INFINITY;
+        }
+        let distance = reachability[area.center()] as f32;
+        // TODO: try to minimize the amount of trees in the footprint
+        // Slopes get terraced, so they're only mildly worse than flat ground
+        wateryness(&level, area) * 30.
+            + unevenness(&level, area) * 0.4
+            + (distance / 100.).powf(1.6)
+    };
+    // Prefer lining the streets, only build elsewhere once there's no lot left
+    let (area, facing) = if let Some(lot) =
+        lots.take_best(&level, reachability, TOWN_LOT_REACH, |lot| cost(lot.area))
+    {
+        (lot.area, Some(lot.facing))
+    } else if let Some(area) = optimize(
         Rect::new_centered(center.block(), ivec2(rand(7..=11), rand(7..=15))),
         |area, temperature| {
             let max_move = (60. * temperature) as i32;
@@ -89,30 +142,96 @@ pub fn plan_house_sys(
             if rand(0.2) {
                 *area = Rect::new_centered(area.center(), area.size().yx())
             }
-
-            if !level.free(*area) {
-                return f32::INFINITY;
-            }
-            let distance = level.reachability[area.center()] as f32;
-            // TODO: try to minimize the amount of trees in the footprint
-            wateryness(&level, *area) * 30.
-                + unevenness(&level, *area)
-                + (distance / 100.).powf(1.6)
+            cost(*area)
         },
         200,
         20,
-    ) else {
+    ) {
+        (area, None)
+    } else {
         return Ok(());
     };
 
     commands.spawn((
         Pos(level.ground(area.center()).as_vec3()),
         Planned(area.into_iter().collect()),
-        HousePlan { area },
+        HousePlan { area, facing },
     ));
     Ok(())
 }
 
+/// Some houses get a fenced garden beside them once they're finished
+pub fn garden_sys(
+    mut commands: Commands,
+    mut level: ResMut<Level>,
+    houses: Query<&HousePlan, (With<House>, Added<Built>)>,
+) {
+    for house in &houses {
+        if !rand(0.5) {
+            continue;
+        }
+        let area = house.area;
+        let depth = rand(4..=6);
+        // Leave a gap between the house and the garden, the gate opens into it
+        let first = rand(0..4);
+        let Some((garden, gate, facing)) = (0..4)
+            .map(|i| HDir::ALL[(first + i) % 4])
+            .map(|dir| {
+                let center = area.center();
+                let (garden, gate) = match dir {
+                    XPos => (
+                        Rect::new(
+                            ivec2(area.max.x + 2, area.min.y),
+                            ivec2(area.max.x + 1 + depth, area.max.y),
+                        ),
+                        ivec2(area.max.x + 2, center.y),
+                    ),
+                    XNeg => (
+                        Rect::new(
+                            ivec2(area.min.x - 2, area.min.y),
+                            ivec2(area.min.x - 1 - depth, area.max.y),
+                        ),
+                        ivec2(area.min.x - 2, center.y),
+                    ),
+                    YPos => (
+                        Rect::new(
+                            ivec2(area.min.x, area.max.y + 2),
+                            ivec2(area.max.x, area.max.y + 1 + depth),
+                        ),
+                        ivec2(center.x, area.max.y + 2),
+                    ),
+                    YNeg => (
+                        Rect::new(
+                            ivec2(area.min.x, area.min.y - 2),
+                            ivec2(area.max.x, area.min.y - 1 - depth),
+                        ),
+                        ivec2(center.x, area.min.y - 2),
+                    ),
+                };
+                (garden, gate, dir)
+            })
+            .find(|&(garden, _, _)| {
+                level.free(garden)
+                    && (wateryness(&level, garden) == 0.)
+                    && (unevenness(&level, garden) < 1.5)
+            })
+        else {
+            continue;
+        };
+
+        (level.blocked)(garden, Blocked);
+        let kind = DividerType::for_biome(level.biome[garden.center()]);
+        let border = Polygon(garden.corners().collect());
+        let mut cons = make_divider(&mut level, border.segments(), kind);
+        cons.extend(make_gate(&mut level, gate, facing, kind));
+        commands.spawn((
+            Pos((level.ground(gate) + IVec3::Z).as_vec3()),
+            ConstructionSite::new(cons),
+            Infrastructure,
+        ));
+    }
+}
+
 // Needed to delay until the post itself has been placed in MC
 #[derive(Component)]
 pub struct SpawnHitchedHorse(IVec3);
@@ -123,26 +242,38 @@ pub fn hitching_post_sys(
     mut dl: ResMut<DesireLines>,
     mut replay: ResMut<Replay>,
     tick: Res<CurrentTick>,
-    center: Query<&Pos, With<CityCenter>>,
+    villagers: Query<&Town>,
+    centers: Query<&Pos, With<CityCenter>>,
     new: Query<&SpawnHitchedHorse, Added<SpawnHitchedHorse>>,
 ) -> Result<()> {
     for new in &new {
         let pos = new.0;
-        replay.command(format!("summon horse {} {} {} {{Tame:1,SaddleItem:{{Count:1,id:\"saddle\"}},Leash:{{X:{0},Y:{3},Z:{2}}}}}", pos.x, pos.z-1, pos.y, pos.z));
+        replay.command(format!(
+            "summon horse {} {} {} {{Tame:1,{},{}}}",
+            pos.x,
+            pos.z - 1,
+            pos.y,
+            version().saddle(),
+            version().leash(pos)
+        ));
     }
     if (tick.0 != 20000) & (tick.0 != 30000) {
         return Ok(());
     }
-    let center = center.single()?.truncate().block();
+    // Busier towns are more likely to get one
+    let Some(town) = villagers.iter().collect_vec().try_choose().copied() else {
+        return Ok(());
+    };
+    let center = centers.get(town.0)?.truncate().block();
     let Some(area) = optimize(
-        Rect::new_centered(level.area().center(), ivec2(5, 5)),
+        Rect::new_centered(center, ivec2(5, 5)),
         |area, temperature| {
             let max_move = (60. * temperature) as i32;
             *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
             if !level.free(*area) || area.grow(4).into_iter().all(|b| level.blocked[b] != Street) {
                 return f32::INFINITY;
             }
-            let path = pathfind_street(&level, *area);
+            let path = pathfind_street(&level, *area, None);
             if !path.success {
                 return f32::INFINITY;
             }
@@ -160,7 +291,7 @@ pub fn hitching_post_sys(
     for column in area {
         level.blocked[column] = Street;
     }
-    for node in pathfind_street(&level, area.shrink(1)).path {
+    for node in pathfind_street(&level, area.shrink(1), None).path {
         for (x_off, y_off) in (-1..=1).cartesian_product(-1..=1) {
             level.blocked[node.pos.truncate() + ivec2(x_off, y_off)] = Street;
         }
@@ -172,8 +303,11 @@ pub fn hitching_post_sys(
     let pos = level.ground(area.center());
     let species = level.biome[pos].random_tree_species();
     level(pos, Full(Cobble));
-    level(pos + IVec3::Z, Fence(Wood(species)));
-    level(pos + 2 * IVec3::Z, Fence(Wood(species)));
+    for z in 1..=2 {
+        level(pos + z * IVec3::Z, |prev: Block| {
+            Fence(Wood(species)).waterlogged_in(prev)
+        });
+    }
 
     let hay = level.ground(area.center() + ivec2(2, 1)) + IVec3::Z;
     level(hay, Hay);
@@ -186,13 +320,17 @@ pub fn hitching_post_sys(
 pub fn assign_builds_sys(
     mut commands: Commands,
     mut level: ResMut<Level>,
-    construction_sites: Query<(), With<ConstructionSite>>,
+    construction_sites: Query<(), (With<ConstructionSite>, Without<Infrastructure>)>,
     houses: Query<(), (With<HousePlan>, Without<Planned>)>,
     planned_houses: Query<(Entity, &Planned), With<HousePlan>>,
     lumberjacks: Query<(), (With<LumberjackShack>, Without<Planned>)>,
     planned_lumberjacks: Query<(Entity, &Planned), With<LumberjackShack>>,
     quarries: Query<(), (With<Quarry>, Without<Planned>)>,
     planned_quarries: Query<(Entity, &Planned), With<Quarry>>,
+    kilns: Query<(), (With<BrickKiln>, Without<Planned>)>,
+    planned_kilns: Query<(Entity, &Planned), With<BrickKiln>>,
+    fields: Query<(), (With<Field>, Without<Planned>)>,
+    planned_fields: Query<(Entity, &Planned), With<Field>>,
 ) {
     if construction_sites.iter().len() > 10 {
         return;
@@ -207,6 +345,12 @@ pub fn assign_builds_sys(
     if quarries.iter().len() < 3 {
         plans.extend(&planned_quarries);
     }
+    if kilns.iter().len() < 2 {
+        plans.extend(&planned_kilns);
+    }
+    if fields.iter().len() < 15 {
+        plans.extend(&planned_fields);
+    }
     if let Some(&(selected, area)) = plans.try_choose() {
         if !level.free(area.iter().copied()) {
             commands.entity(selected).despawn();
@@ -220,6 +364,9 @@ pub fn assign_builds_sys(
     }
 }
 
+/// Roughly enough for the walls of a ground floor
+const HOUSE_BRICKS: f32 = 128.;
+
 // TMP
 pub fn test_build_house_sys(
     mut commands: Commands,
@@ -228,25 +375,29 @@ pub fn test_build_house_sys(
     mut untree: Untree,
     new: Query<(Entity, &HousePlan), With<ToBeBuild>>,
     taverns: Query<(), With<Tavern>>,
+    brick_stock: BrickStock,
 ) {
     if let Some((entity, house)) = new.iter().next() {
         // Tmp
-        let tavern = taverns.is_empty() && rand(0.3);
+        let tavern = (taverns.is_empty() && rand(0.3)).then(tavern_name);
+        let bricks = brick_stock.spare() >= HOUSE_BRICKS;
         let (rec, house) = house::house(
             &mut commands,
             &mut level,
             &mut dl,
             &mut untree,
             house.area,
-            tavern,
+            house.facing,
+            tavern.as_deref(),
+            bricks,
         );
         let site = ConstructionSite::new(rec);
         commands
             .entity(entity)
             .remove::<ToBeBuild>()
             .insert((site, house));
-        if tavern {
-            commands.entity(entity).insert(Tavern);
+        if let Some(name) = tavern {
+            commands.entity(entity).insert(Tavern { name });
         }
     }
 }
@@ -0,0 +1,210 @@
+use crate::*;
+use sim::*;
+
+use self::{
+    building_plan::{House, HousePlan, Tavern},
+    lumberjack::LumberjackShack,
+    quarry::Quarry,
+    social::{Arrival, make_name},
+};
+
+const TICKS_PER_SEASON: i32 = 2500;
+const SEASONS: [&str; 4] = ["Spring", "Summer", "Autumn", "Winter"];
+/// Roughly how many characters fit on a book page
+const PAGE_LENGTH: usize = 180;
+
+/// Dated record of notable events, placed as a written book at the end of the simulation
+#[derive(Resource)]
+pub struct Chronicle {
+    town: String,
+    founding_year: i32,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    tick: i32,
+    event: Event,
+}
+
+enum Event {
+    Text(String),
+    /// Merged per season
+    Arrivals(u32),
+}
+
+impl Chronicle {
+    pub fn new(town: String, founding_year: i32) -> Self {
+        Self {
+            town,
+            founding_year,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn date(&self, tick: i32) -> String {
+        let season = tick / TICKS_PER_SEASON;
+        format!(
+            "{} {}",
+            SEASONS[(season % 4) as usize],
+            self.founding_year + season / 4
+        )
+    }
+
+    /// Also announces the event in the replay
+    pub fn record(&mut self, replay: &mut Replay, tick: i32, text: String) {
+        replay.say(&format!("{}: {text}", self.date(tick)), Yellow);
+        self.entries.push(Entry {
+            tick,
+            event: Event::Text(text),
+        });
+    }
+
+    /// All towns start growing at the first tick, so that's when they are founded
+    pub fn record_founding(&mut self, replay: &mut Replay, town: &str) {
+        self.record(replay, 0, format!("Founding of {town}"));
+    }
+
+    fn record_arrival(&mut self, tick: i32) {
+        if let Some(Entry {
+            tick: prev,
+            event: Event::Arrivals(count),
+        }) = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| matches!(entry.event, Event::Arrivals(_)))
+            && (*prev / TICKS_PER_SEASON == tick / TICKS_PER_SEASON)
+        {
+            *count += 1;
+            return;
+        }
+        self.entries.push(Entry {
+            tick,
+            event: Event::Arrivals(1),
+        });
+    }
+
+    fn pages(&self) -> Vec<String> {
+        let mut pages = Vec::new();
+        let mut page = format!("Chronicle of {}\n\n", self.town);
+        let mut prev_date = String::new();
+        for entry in &self.entries {
+            let date = self.date(entry.tick);
+            let mut paragraph = String::new();
+            if date != prev_date {
+                paragraph += &format!("{date}\n");
+                prev_date = date;
+            }
+            match &entry.event {
+                Event::Text(text) => paragraph += text,
+                Event::Arrivals(1) => paragraph += "A settler arrived",
+                Event::Arrivals(count) => paragraph += &format!("{count} settlers arrived"),
+            }
+            paragraph += "\n\n";
+            if !page.is_empty() & (page.len() + paragraph.len() > PAGE_LENGTH) {
+                pages.push(std::mem::take(&mut page));
+            }
+            page += &paragraph;
+        }
+        pages.push(page);
+        pages
+    }
+
+    /// Item snbt of the chronicle as a written book
+    fn book(&self) -> String {
+        let mut title = format!("Chronicle of {}", self.town);
+        if title.len() > 32 {
+            title = "Chronicle".into();
+        }
+        version().written_book(&title, make_name().as_str(), &self.pages())
+    }
+}
+
+pub fn chronicle_sys(
+    mut chronicle: ResMut<Chronicle>,
+    mut replay: ResMut<Replay>,
+    tick: Res<CurrentTick>,
+    mut first_house_built: Local<bool>,
+    arrivals: Query<(), Added<Arrival>>,
+    new_houses: Query<Option<&Tavern>, (With<House>, Added<Built>)>,
+    new_quarries: Query<(), (With<Quarry>, Added<Built>)>,
+    new_lumberjacks: Query<(), (With<LumberjackShack>, Added<Built>)>,
+) {
+    for _ in &arrivals {
+        chronicle.record_arrival(tick.0);
+    }
+    for tavern in &new_houses {
+        if let Some(tavern) = tavern {
+            let text = format!("The {} opened its doors", tavern.name);
+            chronicle.record(&mut replay, tick.0, text);
+        } else if !*first_house_built {
+            *first_house_built = true;
+            chronicle.record(&mut replay, tick.0, "The first house was built".into());
+        }
+    }
+    for _ in &new_quarries {
+        chronicle.record(&mut replay, tick.0, "A new quarry was opened".into());
+    }
+    for _ in &new_lumberjacks {
+        chronicle.record(&mut replay, tick.0, "A lumber camp was set up".into());
+    }
+}
+
+/// Places the chronicle on a lectern in the tavern, or in a chest on the plaza if there is none
+pub fn write_chronicle(
+    mut level: ResMut<Level>,
+    mut replay: ResMut<Replay>,
+    chronicle: Res<Chronicle>,
+    taverns: Query<(&Pos, &HousePlan), (With<Tavern>, With<Built>)>,
+    centers: Query<(Entity, &Pos), With<CityCenter>>,
+) {
+    let cursor = level.recording_cursor();
+    let book = chronicle.book();
+    if let Some((pos, facing)) = taverns
+        .iter()
+        .find_map(|(pos, plan)| lectern_spot(&level, plan.area, pos.block().z))
+    {
+        level(
+            pos,
+            Lectern(facing, true),
+            format!("Book:{{{book}}},Page:0"),
+        );
+    } else if let Some((_, center)) = centers.iter().min_by_key(|(entity, _)| *entity) {
+        let pos = center.block() + IVec3::Z;
+        level(
+            pos,
+            Chest(HDir::YPos),
+            format!("Items:[{{Slot:13b,{book}}}]"),
+        );
+    }
+    for set in level.pop_recording(cursor) {
+        replay.block(set.pos, set.block, set.nbt);
+    }
+}
+
+/// Find a spot against a wall on the ground floor
+fn lectern_spot(level: &Level, area: Rect, ground: i32) -> Option<(IVec3, HDir)> {
+    for z in ground - 2..ground + 4 {
+        for column in area.shrink(1) {
+            let pos = column.extend(z);
+            if !level(pos - IVec3::Z).solid()
+                | (level(pos) != Air)
+                | (level(pos + IVec3::Z) != Air)
+                | HDir::ALL
+                    .iter()
+                    .any(|dir| matches!(level(pos + IVec3::from(*dir)), Door(..)))
+            {
+                continue;
+            }
+            for dir in HDir::ALL {
+                if level(pos + IVec3::from(dir)).solid()
+                    && (level(pos - IVec3::from(dir)) == Air)
+                    && (level(pos - IVec3::from(dir) - IVec3::Z).solid())
+                {
+                    return Some((pos, dir.rotated(2)));
+                }
+            }
+        }
+    }
+    None
+}
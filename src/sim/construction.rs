@@ -10,6 +10,10 @@ pub struct BuildTask {
 #[derive(Component)]
 pub struct Built;
 
+/// Roads, stairs and the like, which don't count towards the limit on construction sites
+#[derive(Component)]
+pub struct Infrastructure;
+
 #[derive(Component, Debug)]
 pub struct ConstructionSite {
     pub todo: ConsList,
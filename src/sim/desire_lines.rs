@@ -29,6 +29,64 @@ pub fn desire_lines_sys(
     }
 }
 
+/// How worn a path has to be for the village to build stairs up a step
+const STAIR_WEAR: i32 = 40;
+
+/// Replaces steps that heavily used paths climb by stone stairs
+pub fn plan_stairs_sys(
+    mut commands: Commands,
+    tick: Res<CurrentTick>,
+    mut level: ResMut<Level>,
+    dl: Res<DesireLines>,
+) {
+    if tick.0 % 500 != 0 {
+        return;
+    }
+    let mut steps = Vec::new();
+    for column in level.area() {
+        if (dl[column] < STAIR_WEAR) | (level.blocked[column] == Blocked) {
+            continue;
+        }
+        let pos = level.ground(column) + IVec3::Z;
+        if !matches!(level(pos), Air | Slab(Granite | Andesite, Bottom))
+            | level(pos + IVec3::Z).solid()
+            | level(pos + IVec3::Z * 2).solid()
+        {
+            continue;
+        }
+        let up = HDir::ALL.into_iter().find(|&dir| {
+            let higher = column + dir;
+            level.area().contains(higher)
+                && (level.height[higher] == pos.z)
+                && (dl[higher] >= STAIR_WEAR / 2)
+                && !level(higher.extend(pos.z + 2)).solid()
+        });
+        if let Some(dir) = up {
+            steps.push((pos, dir));
+        }
+    }
+
+    // Build the stairs of an area together
+    steps.sort_by_key(|(pos, _)| {
+        let chunk = ChunkIndex::from(pos.truncate());
+        (chunk.0, chunk.1)
+    });
+    for steps in steps
+        .chunk_by(|(a, _), (b, _)| ChunkIndex::from(a.truncate()) == ChunkIndex::from(b.truncate()))
+    {
+        let cursor = level.recording_cursor();
+        for &(pos, dir) in steps {
+            level(pos, Stair(Cobble, dir, Bottom));
+        }
+        let cons = level.pop_recording(cursor).map(ConsItem::Set).collect();
+        commands.spawn((
+            Pos(steps[0].0.as_vec3()),
+            ConstructionSite::new(cons),
+            Infrastructure,
+        ));
+    }
+}
+
 pub fn add_desire_line(level: &mut Level, dl: &mut DesireLines, pos: IVec3) {
     // Broaden the path smoothly
     let alt_pos =
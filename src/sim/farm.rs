@@ -0,0 +1,323 @@
+use crate::*;
+use itertools::Itertools;
+use sim::*;
+
+use self::storage_pile::FoodPile;
+use crate::make_divider::{DividerType, make_divider, make_gate};
+
+/// Chance per tick for a crop to advance one growth stage
+const GROWTH_CHANCE: f32 = 0.0015;
+/// Chance per tick for a household to eat one unit of food
+const EAT_CHANCE: f32 = 0.002;
+const PANTRY_SIZE: f32 = 12.;
+
+#[derive(Component)]
+pub struct Field {
+    area: Rect,
+    crop: Crop,
+    /// The house this field belongs to
+    house: Entity,
+    crops: Vec<IVec3>,
+}
+
+impl Field {
+    fn ripe(&self, level: &Level, pos: IVec3) -> bool {
+        matches!(level(pos), GroundPlant(GroundPlant::Crop(crop, age)) if age == crop.max_age())
+    }
+
+    fn ready_for_harvest(&self, level: &Level) -> bool {
+        let ripe = self
+            .crops
+            .iter()
+            .filter(|pos| self.ripe(level, **pos))
+            .count();
+        ripe as f32 >= self.crops.len() as f32 * 0.6
+    }
+}
+
+#[derive(Component)]
+pub struct Farmer {
+    workplace: Entity,
+    ready_to_work: bool,
+}
+
+/// Houses that get supplied with food
+#[derive(Component)]
+pub struct Pantry;
+
+pub fn plan_field_sys(
+    mut commands: Commands,
+    level: Res<Level>,
+    planned: Query<(), (With<Field>, With<Planned>)>,
+    fields: Query<&Field>,
+    houses: Query<(Entity, &Pos), (With<House>, With<Built>)>,
+) {
+    if !planned.is_empty() {
+        return;
+    }
+
+    let Some(&(house, house_pos)) = houses
+        .iter()
+        .filter(|(house, _)| fields.iter().all(|field| field.house != *house))
+        .collect_vec()
+        .try_choose()
+    else {
+        return;
+    };
+    let house_pos = house_pos.truncate();
+
+    let Some(area) = optimize(
+        Rect::new_centered(house_pos.block(), ivec2(rand(5..=9), rand(5..=9))),
+        |area, temperature| {
+            let max_move = (20. * temperature) as i32;
+            *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
+            if rand(0.2) {
+                *area = Rect::new_centered(area.center(), area.size().yx())
+            }
+
+            if !level.free(area.grow(1)) {
+                return f32::INFINITY;
+            }
+            let fertile = area
+                .into_iter()
+                .filter(|column| level(level.ground(*column)).dirtsoil())
+                .count() as f32
+                / area.total() as f32;
+            if fertile < 0.8 {
+                return f32::INFINITY;
+            }
+            let distance = house_pos.distance(area.center_vec2());
+            wateryness(&level, *area) * 20. + unevenness(&level, *area) * 2. + distance / 5.
+        },
+        200,
+        5,
+    ) else {
+        return;
+    };
+
+    commands.spawn((
+        Pos(level.ground(area.center()).as_vec3()),
+        Planned(area.grow(1).into_iter().collect()),
+        Field {
+            area,
+            crop: *[
+                Crop::Wheat,
+                Crop::Wheat,
+                Crop::Carrot,
+                Crop::Potato,
+                Crop::Beetroot,
+            ]
+            .choose(),
+            house,
+            crops: Vec::new(),
+        },
+    ));
+}
+
+pub fn test_build_field_sys(
+    mut commands: Commands,
+    mut level: ResMut<Level>,
+    mut untree: Untree,
+    mut new: Query<(Entity, &mut Field), Added<ToBeBuild>>,
+    houses: Query<&Pos>,
+) {
+    for (entity, mut field) in &mut new {
+        let mut cons = make_field(&mut level, &mut untree, &mut field);
+        // Enclose it, with the gate facing the farmer's house,
+        // unless a street has been laid along it since it was planned
+        let ring = field.area.grow(1);
+        if ring.border().all(|column| level.blocked[column] != Street) {
+            (level.blocked)(ring.border(), Blocked);
+            let kind = DividerType::for_biome(level.biome[ring.center()]);
+            let border = Polygon(ring.corners().collect());
+            cons.extend(make_divider(&mut level, border.segments(), kind));
+            let house = houses
+                .get(field.house)
+                .map_or(ring.center_vec2(), |pos| pos.truncate());
+            let gate = ring
+                .border_no_corners()
+                .min_by_key(|column| column.as_vec2().distance_squared(house) as i32)
+                .unwrap();
+            cons.extend(make_gate(&mut level, gate, ring.outside_face(gate), kind));
+        }
+
+        let site = ConstructionSite::new(cons);
+        commands.entity(entity).remove::<ToBeBuild>().insert(site);
+    }
+}
+
+fn make_field(level: &mut Level, untree: &mut Untree, field: &mut Field) -> ConsList {
+    let cursor = level.recording_cursor();
+    untree.remove_trees(level, field.area.grow(1));
+
+    let well = (field.area.size().min_element() >= 5).then(|| field.area.center());
+    for column in field.area {
+        let pos = level.ground(column);
+        if Some(column) == well {
+            level(pos, Water);
+            continue;
+        }
+        level(pos, Farmland);
+        if let TallPlant(..) = level(pos + 2 * IVec3::Z) {
+            level(pos + 2 * IVec3::Z, Air)
+        }
+        level(
+            pos + IVec3::Z,
+            GroundPlant(GroundPlant::Crop(field.crop, 0)),
+        );
+        field.crops.push(pos + IVec3::Z);
+    }
+
+    level.pop_recording(cursor).map(ConsItem::Set).collect()
+}
+
+pub fn make_food_pile_sys(
+    mut commands: Commands,
+    mut level: ResMut<Level>,
+    mut untree: Untree,
+    new_fields: Query<&Pos, (With<Field>, Added<Built>)>,
+) {
+    for field in &new_fields {
+        let (pos, _, params) = FoodPile::make(&mut level, &mut untree, field.truncate());
+        commands.spawn((
+            Pos(pos),
+            params,
+            OutPile::default(),
+            Pile::new(default(), 2),
+            StoragePile,
+        ));
+    }
+}
+
+pub fn grow_crops_sys(mut level: ResMut<Level>, fields: Query<&Field, With<Built>>) {
+    for field in &fields {
+        for &pos in &field.crops {
+            if let GroundPlant(GroundPlant::Crop(crop, age)) = level(pos)
+                && (age < crop.max_age())
+                && rand(GROWTH_CHANCE)
+            {
+                level(pos, GroundPlant(GroundPlant::Crop(crop, age + 1)))
+            }
+        }
+    }
+}
+
+pub fn assign_worker_sys(
+    mut commands: Commands,
+    available: Query<(Entity, &Pos), With<Jobless>>,
+    unstaffed: Query<(Entity, &Pos), (With<Field>, With<Built>, Without<Staffed>)>,
+) {
+    let mut assigned = Vec::new();
+    for (workplace, pos) in &unstaffed {
+        let Some((worker, _)) = available
+            .iter()
+            .filter(|(e, _)| !assigned.contains(e))
+            .min_by_key(|(_, p)| p.distance_squared(pos.0) as i32)
+        else {
+            continue;
+        };
+        assigned.push(worker);
+        commands.entity(workplace).insert(Staffed);
+        commands.entity(worker).remove::<Jobless>().insert(Farmer {
+            workplace,
+            ready_to_work: true,
+        });
+    }
+}
+
+pub fn work_sys(
+    mut commands: Commands,
+    mut level: ResMut<Level>,
+    pos: Query<&Pos>,
+    mut workers: Query<
+        (Entity, &Villager, &mut Farmer),
+        (Without<PlaceTask>, Without<DeliverTask>, Without<MoveTask>),
+    >,
+    fields: Query<&Field>,
+    piles: Query<(Entity, &Pos, &Pile, &FoodPile), With<StoragePile>>,
+) {
+    for (worker, villager, mut farmer) in &mut workers {
+        let worker_pos = pos.get(worker).unwrap();
+        if farmer.ready_to_work {
+            let field = fields.get(farmer.workplace).unwrap();
+            if !field.ready_for_harvest(&level) {
+                continue;
+            }
+            // Harvest row by row, replanting right away
+            let mut place = PlaceTask(default());
+            let mut amount = 0.;
+            for (_, row) in &field.crops.iter().group_by(|pos| pos.y) {
+                let row = row
+                    .copied()
+                    .filter(|pos| field.ripe(&level, *pos))
+                    .collect_vec();
+                let Some(&first) = row.first() else {
+                    continue;
+                };
+                place.push_back(ConsItem::Goto(MoveTask {
+                    goal: first,
+                    distance: 1,
+                }));
+                place.push_back(ConsItem::Command(playsound("block.crop.break", first)));
+                let cursor = level.recording_cursor();
+                for pos in row {
+                    level(pos, GroundPlant(GroundPlant::Crop(field.crop, 0)));
+                    amount += 0.5;
+                }
+                level.pop_recording_into(&mut place, cursor);
+            }
+            place.push_back(ConsItem::Carry(Some(Stack::new(Good::Food, amount))));
+            commands.entity(worker).insert(place);
+            farmer.ready_to_work = false;
+        } else if let Some(stack) = villager.carry {
+            // Drop off harvest
+            if let Some((to, _, _, _)) = piles
+                .iter()
+                .filter(|(_, pile_pos, current, food_pile)| {
+                    current.space_available(
+                        Good::Food,
+                        food_pile.max(),
+                        min_walk_ticks(worker_pos.0, pile_pos.0),
+                    ) >= stack.amount
+                })
+                .min_by_key(|(_, pos, _, _)| pos.distance(worker_pos.0) as i32)
+            {
+                commands.entity(worker).insert(DeliverTask { to });
+            }
+        } else {
+            // Return to the field
+            commands.entity(worker).insert(MoveTask {
+                goal: pos.get(farmer.workplace).unwrap().block(),
+                distance: 2,
+            });
+            farmer.ready_to_work = true;
+        }
+    }
+}
+
+pub fn stock_pantries_sys(
+    mut commands: Commands,
+    new_houses: Query<Entity, (With<House>, Added<Built>)>,
+) {
+    for house in &new_houses {
+        let mut requested = Goods::default();
+        requested.add(Stack::new(Good::Food, PANTRY_SIZE));
+        commands.entity(house).insert((
+            Pantry,
+            InPile {
+                requested,
+                priority: None,
+            },
+        ));
+    }
+}
+
+pub fn eat_sys(mut pantries: Query<(&mut Pile, &mut InPile), With<Pantry>>) {
+    for (mut pile, mut in_pile) in &mut pantries {
+        let meal = Stack::new(Good::Food, 1.);
+        if rand(EAT_CHANCE) && pile.goods.has(meal) {
+            pile.goods.remove(meal);
+            in_pile.requested.add(meal);
+        }
+    }
+}
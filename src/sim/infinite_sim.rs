@@ -96,12 +96,14 @@ pub fn generate(world: &mut World) {
 
     // Villagers
     let walk = world.register_system(walk_sys);
+    let resolve_paths = world.register_system(resolve_paths_sys);
     let walk = |world: &mut World, villager: Entity, goal: IVec3, distance: i32| {
         world
             .entity_mut(villager)
             .insert(MoveTask { goal, distance });
         while world.get::<MoveTask>(villager).is_some() {
             world.run_system(walk).unwrap();
+            world.run_system(resolve_paths).unwrap();
             world.run_system(tick).unwrap();
         }
     };
@@ -132,7 +134,7 @@ pub fn generate(world: &mut World) {
     for &villager in &villagers {
         world
             .entity_mut(villager)
-            .remove::<(MoveTask, MovePath, InBoat)>();
+            .remove::<(MoveTask, MovePath, PathRequest, InBoat)>();
     }
     for (villager_id, villager) in villagers.into_iter().enumerate() {
         world.get_mut::<Villager>(villager).unwrap().carry = None;
@@ -228,11 +230,17 @@ pub fn generate(world: &mut World) {
         .iter(world)
         .map(|p| p.block())
         .collect_vec();
+    // Traders arrive from outside, not via roads paved within the village
+    let inner = world.resource::<Level>().area().shrink(16);
     let mut road_starts = world
         .resource::<Roads>()
         .0
         .iter()
-        .filter(|path| (path.len() > 60) & (path.iter().all(|n| !n.boat)))
+        .filter(|path| {
+            (path.len() > 60)
+                & path.iter().all(|n| !n.boat)
+                & !inner.contains(path.back().unwrap().pos.truncate())
+        })
         .map(|p| p.back().unwrap().pos)
         .collect_vec();
 
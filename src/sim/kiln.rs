@@ -0,0 +1,264 @@
+//! Brick kilns fire soil delivered to them into bricks
+
+use crate::*;
+use bevy_ecs::system::SystemParam;
+use itertools::Itertools;
+use sim::*;
+
+use self::lots::{Lot, Lots, TOWN_LOT_REACH};
+
+/// Soil fired at once
+const BATCH: f32 = 16.;
+const FIRING_TICKS: i32 = 300;
+
+#[derive(Component)]
+pub struct BrickKiln {
+    pub area: Rect,
+    /// Where the fire is fed
+    pub facing: HDir,
+    flue: IVec3,
+    /// Ticks until the current batch is done
+    firing: i32,
+}
+
+#[derive(Component)]
+pub struct Brickmaker {
+    workplace: Entity,
+    at_kiln: bool,
+}
+
+/// Bricks in stock, minus the ones construction sites are already waiting for
+#[derive(SystemParam)]
+pub struct BrickStock<'w, 's> {
+    piles: Query<'w, 's, (&'static Pile, &'static OutPile)>,
+    in_piles: Query<'w, 's, &'static InPile>,
+    new_sites: Query<'w, 's, &'static ConstructionSite, Without<InPile>>,
+}
+
+impl BrickStock<'_, '_> {
+    pub fn spare(&self) -> f32 {
+        let stock: f32 = self
+            .piles
+            .iter()
+            .map(|(pile, out_pile)| {
+                pile.available(Good::Brick, 0)
+                    - out_pile.reserved.get(&Good::Brick).copied().unwrap_or(0.)
+            })
+            .sum();
+        let requested: f32 = self
+            .in_piles
+            .iter()
+            .filter_map(|in_pile| in_pile.requested.get(&Good::Brick))
+            .sum();
+        // Sites only request their materials once new_construction_site_sys has run
+        let committed: f32 = self
+            .new_sites
+            .iter()
+            .flat_map(|site| &site.todo)
+            .filter_map(|item| match item {
+                ConsItem::Set(set) => goods_for_block(set.block),
+                _ => None,
+            })
+            .filter(|stack| stack.good == Good::Brick)
+            .map(|stack| stack.amount)
+            .sum();
+        stock - requested - committed
+    }
+}
+
+pub fn plan_kiln_sys(
+    mut commands: Commands,
+    level: Res<Level>,
+    mut lots: ResMut<Lots>,
+    planned: Query<(), (With<BrickKiln>, With<Planned>)>,
+    villagers: Query<&Town>,
+    centers: Query<(&Pos, &Reachability), With<CityCenter>>,
+) -> Result<()> {
+    if !planned.is_empty() {
+        return Ok(());
+    }
+
+    let Some(town) = villagers.iter().collect_vec().try_choose().copied() else {
+        return Ok(());
+    };
+    let (center, reachability) = centers.get(town.0)?;
+
+    let cost = |area: Rect| {
+        if !level.free(area.grow(1)) {
+            return f32::INFINITY;
+        }
+        let center_distance = reachability[area.center()] as f32;
+        wateryness(&level, area) * 20. + unevenness(&level, area) * 1. + center_distance / 200.
+    };
+    // Keep a margin within the lot
+    let kiln = |lot: Lot| lot.front(7, 7).shrink(1);
+
+    let (area, facing) = if let Some(lot) =
+        lots.take_best(&level, reachability, TOWN_LOT_REACH, |lot| cost(kiln(lot)))
+    {
+        (kiln(lot), lot.facing)
+    } else if let Some(area) = optimize(
+        Rect::new_centered(center.0.block().truncate(), IVec2::splat(5)),
+        |area, temperature| {
+            let max_move = (60. * temperature) as i32;
+            *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
+            cost(*area)
+        },
+        200,
+        1,
+    ) {
+        (area, HDir::ALL[rand(0..4)])
+    } else {
+        return Ok(());
+    };
+
+    commands.spawn((
+        Pos(level.ground(area.center()).as_vec3()),
+        Planned(area.grow(1).into_iter().collect()),
+        BrickKiln {
+            area,
+            facing,
+            flue: IVec3::ZERO,
+            firing: 0,
+        },
+    ));
+    Ok(())
+}
+
+pub fn test_build_kiln_sys(
+    mut commands: Commands,
+    mut level: ResMut<Level>,
+    mut untree: Untree,
+    mut new: Query<(Entity, &mut Pos, &mut BrickKiln), Added<ToBeBuild>>,
+) {
+    for (entity, mut pos, mut kiln) in &mut new {
+        let floor = level.height.average(kiln.area.border()).round() as i32;
+        let center = kiln.area.center();
+        kiln.flue = center.extend(floor + 4);
+        // Deliveries and the brickmaker go in front of the mouth
+        pos.0 = (center + kiln.facing.offset(2, 0))
+            .extend(floor + 1)
+            .as_vec3();
+        commands
+            .entity(entity)
+            .remove::<ToBeBuild>()
+            .insert(ConstructionSite::new(make_kiln(
+                &mut level,
+                &mut untree,
+                &kiln,
+                floor,
+            )));
+    }
+}
+
+fn make_kiln(level: &mut Level, untree: &mut Untree, kiln: &BrickKiln, floor: i32) -> ConsList {
+    let cursor = level.recording_cursor();
+    untree.remove_trees(level, kiln.area);
+
+    for column in kiln.area {
+        let base = level.height[column].min(floor);
+        level.fill_at(Some(column), base..=floor, PackedMud);
+        level.height[column] = floor;
+        level.fill_at(Some(column), floor + 1..floor + 6, Air);
+    }
+
+    let center = kiln.area.center();
+    for column in Rect::new_centered(center, IVec2::splat(3)).border() {
+        level.fill_at(Some(column), floor + 1..=floor + 3, Full(MudBrick));
+        level(column.extend(floor + 4), Slab(MudBrick, Bottom));
+    }
+    // Mouth, the flue is left open
+    level((center + kiln.facing.offset(1, 0)).extend(floor + 1), Air);
+
+    level.pop_recording(cursor).map(ConsItem::Set).collect()
+}
+
+pub fn request_soil_sys(
+    mut commands: Commands,
+    new: Query<Entity, (With<BrickKiln>, Added<Built>)>,
+) {
+    for kiln in &new {
+        let mut requested = Goods::default();
+        requested.add(Stack::new(Good::Soil, BATCH * 2.));
+        commands.entity(kiln).insert(InPile {
+            requested,
+            priority: None,
+        });
+    }
+}
+
+pub fn assign_worker_sys(
+    mut commands: Commands,
+    available: Query<(Entity, &Pos), With<Jobless>>,
+    unstaffed: Query<(Entity, &Pos), (With<BrickKiln>, With<Built>, Without<Staffed>)>,
+) {
+    let mut assigned = Vec::new();
+    for (workplace, pos) in &unstaffed {
+        let Some((worker, _)) = available
+            .iter()
+            .filter(|(e, _)| !assigned.contains(e))
+            .min_by_key(|(_, p)| p.distance_squared(pos.0) as i32)
+        else {
+            continue;
+        };
+        assigned.push(worker);
+        commands.entity(workplace).insert(Staffed);
+        commands
+            .entity(worker)
+            .remove::<Jobless>()
+            .insert(Brickmaker {
+                workplace,
+                at_kiln: false,
+            });
+    }
+}
+
+pub fn work_sys(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    mut workers: Query<
+        (Entity, &mut Brickmaker),
+        (Without<PlaceTask>, Without<DeliverTask>, Without<MoveTask>),
+    >,
+    mut kilns: Query<(&Pos, &mut BrickKiln, &mut Pile, &mut InPile)>,
+) {
+    for (worker, mut brickmaker) in &mut workers {
+        let Ok((pos, mut kiln, mut pile, mut in_pile)) = kilns.get_mut(brickmaker.workplace) else {
+            continue;
+        };
+        if !brickmaker.at_kiln {
+            commands.entity(worker).insert(MoveTask {
+                goal: pos.block(),
+                distance: 1,
+            });
+            brickmaker.at_kiln = true;
+            continue;
+        }
+        let batch = Stack::new(Good::Soil, BATCH);
+        if (kiln.firing > 0) | !pile.goods.has(batch) {
+            continue;
+        }
+        // Load the kiln and light it
+        pile.goods.remove(batch);
+        in_pile.requested.add(batch);
+        pile.add_at(Stack::new(Good::Brick, BATCH), FIRING_TICKS);
+        kiln.firing = FIRING_TICKS;
+        replay.command(playsound("item.firecharge.use", pos.block()));
+    }
+}
+
+pub fn fire_sys(mut replay: ResMut<Replay>, mut kilns: Query<&mut BrickKiln>) {
+    for mut kiln in &mut kilns {
+        if kiln.firing == 0 {
+            continue;
+        }
+        kiln.firing -= 1;
+        if kiln.firing % 10 == 0 {
+            let flue = kiln.flue;
+            replay.command(format!(
+                "particle campfire_cosy_smoke {} {} {} 0.1 0.2 0.1 0.01 2",
+                flue.x, flue.z, flue.y
+            ));
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use std::ops::DerefMut;
 
 use super::*;
-use crate::{goods::Good, *};
+use crate::{goods::Good, pathfind::PathingNode, route_planner::RoutePlanner, *};
 
 use bevy_ecs::prelude::*;
+use itertools::Itertools;
+use rayon::prelude::*;
 use storage_pile::UpdatePileVisuals;
 
 #[derive(Component, Debug, Clone, Copy)]
@@ -175,22 +177,26 @@ pub struct DeliverReady;
 
 pub fn pickup_sys(
     mut commands: Commands,
-    level: Res<Level>,
     pos: Query<&Pos>,
     mut out_piles: Query<(&mut Pile, &mut OutPile)>,
     mut pickup: Query<(Entity, &mut Villager, &PickupTask, Has<PickupReady>), Without<MoveTask>>,
 ) {
     for (entity, mut villager, task, pickup_ready) in &mut pickup {
         if !pickup_ready {
-            let (mut pile, mut out_pile) = out_piles.get_mut(task.from).unwrap();
+            let (pile, mut out_pile) = out_piles.get_mut(task.from).unwrap();
             let goal = pos.get(task.from).unwrap().block();
             let distance = pile.interact_distance;
-            let path = MovePath::new(&level, pos.get(entity).unwrap().block(), goal, distance);
-            pile.add_at(-task.stack, path.ticks() + 2);
             *out_pile.reserved.get_mut(&task.stack.good).unwrap() -= task.stack.amount;
+            let request = PathRequest {
+                arrival: Some(PileArrival {
+                    pile: task.from,
+                    stack: -task.stack,
+                    extra_ticks: 2,
+                }),
+            };
             commands
                 .entity(entity)
-                .insert((path, MoveTask { goal, distance }, PickupReady));
+                .insert((request, MoveTask { goal, distance }, PickupReady));
         } else if villager.carry.is_none() {
             commands.trigger(UpdatePileVisuals { entity: task.from });
             let (mut pile, out_pile) = out_piles.get_mut(task.from).unwrap();
@@ -215,7 +221,6 @@ pub fn pickup_sys(
 pub fn deliver_sys(
     mut commands: Commands,
     mut replay: ResMut<Replay>,
-    level: Res<Level>,
     pos: Query<&Pos>,
     mut piles: Query<(&mut Pile, Option<&mut InPile>)>,
     mut deliver: Query<
@@ -231,17 +236,22 @@ pub fn deliver_sys(
         if !deliver_ready {
             let goal = pos.get(task.to).unwrap().block();
             let distance = piles.get(task.to).unwrap().0.interact_distance;
-            let path = MovePath::new(&level, pos.get(entity).unwrap().block(), goal, distance);
-            let (mut pile, in_pile) = piles.get_mut(task.to).unwrap();
-            pile.add_at(stack, path.ticks());
+            let (_, in_pile) = piles.get_mut(task.to).unwrap();
             if let Some(mut in_pile) = in_pile
                 && in_pile.priority == Some(stack.good)
             {
                 in_pile.priority = None
             }
+            let request = PathRequest {
+                arrival: Some(PileArrival {
+                    pile: task.to,
+                    stack,
+                    extra_ticks: 0,
+                }),
+            };
             commands
                 .entity(entity)
-                .insert((MoveTask { goal, distance }, path, DeliverReady));
+                .insert((MoveTask { goal, distance }, request, DeliverReady));
         } else {
             commands.trigger(UpdatePileVisuals { entity: task.to });
             replay.command(playsound("drop", pos.get(entity).unwrap().block()));
@@ -254,6 +264,61 @@ pub fn deliver_sys(
     }
 }
 
+/// Pile change to apply once [`resolve_paths_sys`] has found the path for the [`MoveTask`]
+#[derive(Component, Default)]
+#[component(storage = "SparseSet")]
+pub struct PathRequest {
+    pub arrival: Option<PileArrival>,
+}
+
+/// Change to a pile once the path has been walked
+pub struct PileArrival {
+    pub pile: Entity,
+    pub stack: Stack,
+    /// After arriving
+    pub extra_ticks: i32,
+}
+
+/// Resolves the paths of all move tasks issued this tick in parallel
+pub fn resolve_paths_sys(
+    mut commands: Commands,
+    level: Res<Level>,
+    mut planner: ResMut<RoutePlanner>,
+    requests: Query<(Entity, &Pos, &MoveTask, Option<&PathRequest>), Without<MovePath>>,
+    mut piles: Query<&mut Pile>,
+) {
+    // Results are applied in entity order, so that they don't depend on thread scheduling
+    let requests = requests
+        .iter()
+        .sorted_by_key(|(entity, ..)| *entity)
+        .collect_vec();
+    if requests.is_empty() {
+        return;
+    }
+    let searches = planner.pathfind_batch(
+        &level,
+        &requests
+            .iter()
+            .map(|(_, pos, task, _)| (pos.block(), task.goal, task.distance))
+            .collect_vec(),
+    );
+    let paths = requests
+        .par_iter()
+        .zip(searches)
+        .map(|((_, pos, _, _), search)| MovePath::new(&level, pos.block(), search.path))
+        .collect::<Vec<_>>();
+    for ((entity, _, _, request), path) in requests.into_iter().zip(paths) {
+        if let Some(PathRequest {
+            arrival: Some(arrival),
+        }) = request
+        {
+            let mut pile = piles.get_mut(arrival.pile).unwrap();
+            pile.add_at(arrival.stack, path.ticks() + arrival.extra_ticks);
+        }
+        commands.entity(entity).remove::<PathRequest>().insert(path);
+    }
+}
+
 const WALK_PER_TICK: f32 = 0.16;
 const BOATING_PER_TICK: f32 = 0.2;
 const CLIMB_PER_TICK: f32 = 0.09;
@@ -267,8 +332,7 @@ pub fn min_walk_ticks(start: Vec3, end: Vec3) -> i32 {
 pub struct MovePath(VecDeque<MovePathNode>);
 
 impl MovePath {
-    fn new(level: &Level, start: IVec3, goal: IVec3, target_distance: i32) -> Self {
-        let mut path = pathfind(level, start, goal, target_distance).path;
+    fn new(level: &Level, start: IVec3, mut path: VecDeque<PathingNode>) -> Self {
         let mut steps = VecDeque::<MovePathNode>::new();
         let mut pos = start.as_vec3();
         let mut vertical = false;
@@ -340,23 +404,29 @@ pub fn walk_sys(
         &MoveTask,
         Option<&InBoat>,
         Option<&mut MovePath>,
+        Option<&PathRequest>,
     )>,
+    mut piles: Query<&mut Pile>,
     config: Res<Config>,
 ) {
-    for (entity, id, mut pos, goal, in_boat, path) in &mut query {
+    for (entity, id, mut pos, goal, in_boat, path, request) in &mut query {
         if config.skip_walk {
             pos.0 = goal.goal.as_vec3();
-            commands.entity(entity).remove::<(MoveTask, MovePath)>();
+            if let Some(PathRequest {
+                arrival: Some(arrival),
+            }) = request
+            {
+                let mut pile = piles.get_mut(arrival.pile).unwrap();
+                pile.add_at(arrival.stack, arrival.extra_ticks);
+            }
+            commands
+                .entity(entity)
+                .remove::<(MoveTask, MovePath, PathRequest)>();
             continue;
         }
 
+        // Waiting for resolve_paths_sys
         let Some(mut path) = path else {
-            commands.entity(entity).insert(MovePath::new(
-                &level,
-                pos.block(),
-                goal.goal,
-                goal.distance,
-            ));
             continue;
         };
 
@@ -372,13 +442,13 @@ pub fn walk_sys(
                 let boat_id = Id::default();
                 commands.entity(entity).insert(InBoat(boat_id));
                 let biome = level.biome[pos.block().truncate()];
+                let (boat, boat_type) = version().boat(biome.default_tree_species());
                 replay.command(format!(
-                    "summon boat {} {} {} {{{}, Invulnerable:1, Type:\"{}\"}}",
+                    "summon {boat} {} {} {} {{{}, Invulnerable:1{boat_type}}}",
                     pos.x,
                     pos.z,
                     pos.y,
                     boat_id.snbt(),
-                    biome.default_tree_species().to_str()
                 ));
                 replay.command(format!("ride {id} mount {boat_id}"));
             }
@@ -0,0 +1,149 @@
+//! Subdivides the land along roads into lots, so buildings line the streets
+//! instead of being scattered about
+
+use super::*;
+use bevy_math::FloatOrd;
+
+use self::roads::Roads;
+
+/// Keeps neighboring buildings from touching
+const LOT_GAP: i32 = 2;
+/// How far from the center of a road its street can extend
+const MAX_SETBACK: i32 = 6;
+/// Walking cost from the town center within which lots belong to the town,
+/// so that roads leading away don't spread it out
+pub const TOWN_LOT_REACH: u32 = 300;
+
+/// Plot of land with frontage to a road
+#[derive(Debug, Copy, Clone)]
+pub struct Lot {
+    pub area: Rect,
+    /// Towards the road
+    pub facing: HDir,
+}
+
+impl Lot {
+    /// Width along the road
+    pub fn frontage(self) -> i32 {
+        match self.facing {
+            XPos | XNeg => self.area.size().y,
+            YPos | YNeg => self.area.size().x,
+        }
+    }
+
+    /// Distance from the road to the back of the lot
+    pub fn depth(self) -> i32 {
+        match self.facing {
+            XPos | XNeg => self.area.size().x,
+            YPos | YNeg => self.area.size().y,
+        }
+    }
+
+    /// Part of the lot directly at the road, centered along it
+    pub fn front(self, frontage: i32, depth: i32) -> Rect {
+        let frontage = frontage.min(self.frontage());
+        let depth = depth.min(self.depth());
+        let Rect { min, max } = self.area;
+        let center = self.area.center();
+        let (from, to) = (center - frontage / 2, center + (frontage - 1) / 2);
+        match self.facing {
+            XPos => Rect::new(ivec2(max.x - depth + 1, from.y), ivec2(max.x, to.y)),
+            XNeg => Rect::new(ivec2(min.x, from.y), ivec2(min.x + depth - 1, to.y)),
+            YPos => Rect::new(ivec2(from.x, max.y - depth + 1), ivec2(to.x, max.y)),
+            YNeg => Rect::new(ivec2(from.x, min.y), ivec2(to.x, min.y + depth - 1)),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Lots(pub Vec<Lot>);
+
+impl Lots {
+    /// Removes the lot with the lowest cost, if any has a finite one.
+    /// Only considers lots within `max_reach` of the town that are closer to it than to any other.
+    pub fn take_best(
+        &mut self,
+        level: &Level,
+        town: &Reachability,
+        max_reach: u32,
+        cost: impl Fn(Lot) -> f32,
+    ) -> Option<Lot> {
+        let (i, _) = self
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, lot)| {
+                (town[lot.area.center()] <= max_reach) & town.serves(level, lot.area.center())
+            })
+            .map(|(i, lot)| (i, cost(*lot)))
+            .filter(|(_, cost)| cost.is_finite())
+            .min_by_key(|(_, cost)| FloatOrd(*cost))?;
+        Some(self.0.remove(i))
+    }
+}
+
+/// Lays out new lots along the roads, and drops the ones that have been built over
+pub fn subdivide_lots_sys(
+    tick: Res<CurrentTick>,
+    level: Res<Level>,
+    roads: Res<Roads>,
+    mut lots: ResMut<Lots>,
+) {
+    // Roads get paved over time, so periodically check for new frontage
+    if tick.0 % 2000 != 0 {
+        return;
+    }
+    lots.0.retain(|lot| level.free(lot.area));
+
+    for road in &roads.0 {
+        for i in 4..road.len().saturating_sub(4) {
+            // Roads lead off the map and cross rivers
+            if road[i].boat
+                || !level
+                    .area()
+                    .shrink(MAX_SETBACK)
+                    .contains(road[i].pos.truncate())
+            {
+                continue;
+            }
+            let diff = (road[i + 4].pos - road[i - 4].pos).truncate();
+            let along = if diff.x.abs() >= diff.y.abs() {
+                XPos
+            } else {
+                YPos
+            };
+            for side in [along.rotated(1), along.rotated(-1)] {
+                // Lots start where the street ends
+                let mut front = road[i].pos.truncate();
+                for _ in 0..MAX_SETBACK {
+                    if level.blocked[front] != Street {
+                        break;
+                    }
+                    front += side;
+                }
+                if level.blocked[front] == Street {
+                    continue;
+                }
+                let frontage = rand(7..=11);
+                let depth = rand(8..=13);
+                let area = Rect::new(
+                    front - along.offset(frontage / 2, 0),
+                    front + along.offset((frontage - 1) / 2, 0) + side.offset(depth - 1, 0),
+                );
+                if lots
+                    .0
+                    .iter()
+                    .any(|lot| lot.area.grow(LOT_GAP).overlapps(area))
+                    || !level.free(area)
+                    || area.into_iter().any(|c| level.water[c].is_some())
+                {
+                    continue;
+                }
+                lots.0.push(Lot {
+                    area,
+                    facing: side.rotated(2),
+                });
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@ use itertools::Itertools;
 use sim::*;
 
 use self::{
+    lots::{Lot, Lots, TOWN_LOT_REACH},
     storage_pile::LumberPile,
     trees::{Tree, TreeState},
 };
@@ -44,47 +45,63 @@ impl ChopTask {
 pub fn plan_lumberjack_sys(
     mut commands: Commands,
     level: Res<Level>,
+    mut lots: ResMut<Lots>,
     planned: Query<(), (With<LumberjackShack>, With<Planned>)>,
     trees: Query<(Entity, &Pos, &Tree), Without<TreeIsNearLumberCamp>>,
-    center: Query<&Pos, With<CityCenter>>,
+    villagers: Query<&Town>,
+    centers: Query<(&Pos, &Reachability), With<CityCenter>>,
 ) -> Result<()> {
     if !planned.is_empty() {
         return Ok(());
     }
 
+    let Some(town) = villagers.iter().collect_vec().try_choose().copied() else {
+        return Ok(());
+    };
+    let (center, reachability) = centers.get(town.0)?;
+
+    let cost = |area: Rect| {
+        if !level.free(area.grow(1)) {
+            return f32::INFINITY;
+        }
+        let center_distance = reachability[area.center()] as f32;
+        let tree_access = trees
+            .iter()
+            .filter_map(|(_, p, tree)| {
+                (tree.state == TreeState::Ready).then_some(
+                    -1. / ((area.center().as_vec2().distance(p.truncate()) - 10.).max(7.)),
+                )
+            })
+            .sum::<f32>();
+        wateryness(&level, area) * 20.
+            + unevenness(&level, area) * 1.
+            + center_distance / 200.
+            + tree_access * 5.
+    };
+    let (frontage, depth) = (rand(4..=6), rand(5..=8));
+    // Keep a margin within the lot
+    let shack = |lot: Lot| lot.front(frontage + 2, depth + 2).shrink(1);
+
     // TODO: Seperate focus and shack position selection
-    let Some(area) = optimize(
-        Rect::new_centered(
-            center.single()?.0.block().truncate(),
-            ivec2(rand(4..=6), rand(5..=8)),
-        ),
+    let area = if let Some(lot) =
+        lots.take_best(&level, reachability, TOWN_LOT_REACH, |lot| cost(shack(lot)))
+    {
+        shack(lot)
+    } else if let Some(area) = optimize(
+        Rect::new_centered(center.0.block().truncate(), ivec2(frontage, depth)),
         |area, temperature| {
             let max_move = (60. * temperature) as i32;
             *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
             if rand(0.2) {
                 *area = Rect::new_centered(area.center(), area.size().yx())
             }
-
-            if !level.free(area.grow(1)) {
-                return f32::INFINITY;
-            }
-            let center_distance = level.reachability[area.center()] as f32;
-            let tree_access = trees
-                .iter()
-                .filter_map(|(_, p, tree)| {
-                    (tree.state == TreeState::Ready).then_some(
-                        -1. / ((area.center().as_vec2().distance(p.truncate()) - 10.).max(7.)),
-                    )
-                })
-                .sum::<f32>();
-            wateryness(&level, *area) * 20.
-                + unevenness(&level, *area) * 1.
-                + center_distance / 200.
-                + tree_access * 5.
+            cost(*area)
         },
         200,
         1,
-    ) else {
+    ) {
+        area
+    } else {
         return Ok(());
     };
 
@@ -241,15 +258,19 @@ pub fn make_lumber_piles_sys(
     mut commands: Commands,
     mut level: ResMut<Level>,
     mut untree: Untree,
-    center: Query<&Pos, With<CityCenter>>,
+    centers: Query<&Pos, With<CityCenter>>,
     new_lumberjacks: Query<&Pos, Added<LumberjackFocus>>,
 ) -> Result<()> {
     for lumberjack in &new_lumberjacks {
+        let center = centers
+            .iter()
+            .min_by_key(|center| center.distance(lumberjack.0) as i32)
+            .ok_or("No city center")?;
         let (pos, _, params) = LumberPile::make(
             &mut level,
             &mut untree,
             lumberjack.0.truncate(),
-            center.single()?.truncate(),
+            center.truncate(),
         );
         commands.spawn((
             Pos(pos.as_vec3()),
@@ -0,0 +1,149 @@
+//! Statistics about the economy, written as a CSV file next to the output world
+//! so bottlenecks can be plotted.
+
+use std::{fmt::Write, path::Path};
+
+use super::*;
+use crate::goods::Good;
+use crate::lumberjack::ChopTask;
+use crate::pathfind::{PATH_STATS, PathStatsTotals};
+
+const GOODS: [Good; 5] = [Good::Stone, Good::Wood, Good::Soil, Good::Brick, Good::Food];
+
+#[derive(Resource, Default)]
+pub struct Metrics {
+    rows: Vec<Row>,
+    current: Row,
+    site_started: HashMap<Entity, i32>,
+}
+
+/// One interval
+#[derive(Default, Clone)]
+struct Row {
+    /// At the end of the interval
+    tick: i32,
+    /// Summed over each tick of the interval
+    idle_villager_ticks: u32,
+    busy_villager_ticks: u32,
+    /// In piles other than construction sites
+    stock: [f32; GOODS.len()],
+    /// Not yet covered by stock or incoming deliveries
+    requested: [f32; GOODS.len()],
+    construction_sites: u32,
+    /// Of the sites finished during the interval
+    finished_sites: u32,
+    construction_ticks: i64,
+    paths: PathStatsTotals,
+}
+
+impl Metrics {
+    pub fn write(&self, level_path: &Path) {
+        let mut csv = String::from("tick,idle_villagers,busy_villagers");
+        for good in GOODS {
+            write!(csv, ",stock_{good:?}").unwrap();
+        }
+        for good in GOODS {
+            write!(csv, ",requested_{good:?}").unwrap();
+        }
+        csv.push_str(
+            ",construction_sites,finished_sites,avg_construction_ticks,\
+            path_searches,path_failures,avg_path_cost,avg_path_explored\n",
+        );
+        let mut interval_start = 0;
+        for row in &self.rows {
+            let ticks = (row.tick - interval_start).max(1) as f32;
+            interval_start = row.tick;
+            write!(
+                csv,
+                "{},{:.2},{:.2}",
+                row.tick,
+                row.idle_villager_ticks as f32 / ticks,
+                row.busy_villager_ticks as f32 / ticks
+            )
+            .unwrap();
+            for amount in row.stock.iter().chain(&row.requested) {
+                write!(csv, ",{amount:.1}").unwrap();
+            }
+            let average = |total: f32, count: f32| if count > 0. { total / count } else { 0. };
+            writeln!(
+                csv,
+                ",{},{},{:.0},{},{},{:.0},{:.0}",
+                row.construction_sites,
+                row.finished_sites,
+                average(row.construction_ticks as f32, row.finished_sites as f32),
+                row.paths.searches,
+                row.paths.failures,
+                average(row.paths.cost as f32, row.paths.searches as f32),
+                average(row.paths.explored as f32, row.paths.searches as f32),
+            )
+            .unwrap();
+        }
+        std::fs::create_dir_all(level_path).unwrap();
+        std::fs::write(level_path.join("metrics.csv"), csv).expect("Failed to write metrics");
+    }
+}
+
+pub fn metrics_sys(
+    config: Res<Config>,
+    tick: Res<CurrentTick>,
+    mut metrics: ResMut<Metrics>,
+    villagers: Query<(), With<Villager>>,
+    busy: Query<
+        (),
+        (
+            With<Villager>,
+            Or<(
+                With<MoveTask>,
+                With<PickupTask>,
+                With<DeliverTask>,
+                With<BuildTask>,
+                With<PlaceTask>,
+                With<ChopTask>,
+            )>,
+        ),
+    >,
+    piles: Query<&Pile, Without<ConstructionSite>>,
+    in_piles: Query<&InPile>,
+    sites: Query<(), With<ConstructionSite>>,
+    new_sites: Query<Entity, Added<ConstructionSite>>,
+    mut finished_sites: RemovedComponents<ConstructionSite>,
+) {
+    let Some(interval) = config.metrics_interval.filter(|interval| *interval > 0) else {
+        return;
+    };
+    let metrics = &mut *metrics;
+
+    let busy_count = busy.iter().count() as u32;
+    metrics.current.busy_villager_ticks += busy_count;
+    metrics.current.idle_villager_ticks += villagers.iter().count() as u32 - busy_count;
+
+    for site in &new_sites {
+        metrics.site_started.insert(site, tick.0);
+    }
+    for site in finished_sites.read() {
+        if let Some(started) = metrics.site_started.remove(&site) {
+            metrics.current.finished_sites += 1;
+            metrics.current.construction_ticks += (tick.0 - started) as i64;
+        }
+    }
+
+    if (tick.0 + 1) % interval != 0 {
+        return;
+    }
+    let mut row = std::mem::take(&mut metrics.current);
+    row.tick = tick.0 + 1;
+    for (i, good) in GOODS.into_iter().enumerate() {
+        // Summing an empty iterator would give -0
+        row.stock[i] = piles
+            .iter()
+            .filter_map(|pile| pile.goods.get(&good))
+            .fold(0., |total, amount| total + amount);
+        row.requested[i] = in_piles
+            .iter()
+            .filter_map(|in_pile| in_pile.requested.get(&good))
+            .fold(0., |total, amount| total + amount);
+    }
+    row.construction_sites = sites.iter().count() as u32;
+    row.paths = PATH_STATS.take();
+    metrics.rows.push(row);
+}
@@ -5,7 +5,10 @@ use bevy_math::FloatOrd;
 use itertools::Itertools;
 use sim::*;
 
-use self::storage_pile::StonePile;
+use self::{
+    lots::{Lot, Lots, TOWN_LOT_REACH},
+    storage_pile::StonePile,
+};
 
 pub fn quarryable(block: Block) -> bool {
     matches!(
@@ -14,6 +17,9 @@ pub fn quarryable(block: Block) -> bool {
     )
 }
 
+/// Lots are only worth quarrying if there's plenty of stone behind them
+const MIN_LOT_STONE: f32 = 2.;
+
 #[derive(PartialEq, Copy, Clone)]
 struct Params {
     pos: IVec2,
@@ -68,22 +74,94 @@ pub struct Mason {
 pub fn plan_quarry_sys(
     mut commands: Commands,
     level: Res<Level>,
+    mut lots: ResMut<Lots>,
     planned: Query<(), (With<Quarry>, With<Planned>)>,
     others: Query<&Pos, With<Quarry>>,
-    center: Query<&Pos, With<CityCenter>>,
+    villagers: Query<&Town>,
+    centers: Query<(&Pos, &Reachability), With<CityCenter>>,
 ) -> Result<()> {
     if !planned.is_empty() {
         return Ok(());
     }
 
+    let Some(town) = villagers.iter().collect_vec().try_choose().copied() else {
+        return Ok(());
+    };
+    let (center, reachability) = centers.get(town.0)?;
+
     let others = others
         .iter()
         .map(|p| p.0.truncate().as_ivec2())
         .collect_vec();
 
-    let Some(params) = optimize(
+    let too_close = |pos: IVec2| {
+        others
+            .iter()
+            .any(|other| (other - pos).abs().element_sum() < 24)
+    };
+    let distance = |pos: IVec2| {
+        let distance = reachability[pos] as f32 - 650.;
+        // Penalize quarries near city center
+        if distance < 0. {
+            distance * -5.
+        } else {
+            distance
+        }
+    };
+    // Returns the amount of stone too
+    let cost = |params: Params| {
+        if !level.free(params.base_area()) || !level.free(params.probed_mining_area()) {
+            return (f32::INFINITY, 0.);
+        }
+        // TODO: determine floor height here, weighed by towards lower points along border
+        let avg_start_height = level.height.average(params.base_area()) as i32;
+
+        let mut stone = 0;
+        let mut columns = 0;
+        for column in params.probed_mining_area() {
+            columns += 1;
+            for z in avg_start_height..avg_start_height + 15 {
+                match level(column.extend(z)) {
+                    block if quarryable(block) => stone += 1,
+                    other if !other.solid() => break,
+                    _ => (),
+                }
+            }
+        }
+        let avg_stone = stone as f32 / columns as f32;
+
+        let area = Rect::new_centered(params.pos, IVec2::splat(7));
+        if area.into_iter().any(|c| level.water[c].is_some()) {
+            return (f32::INFINITY, avg_stone);
+        }
+        /*wateryness(&level, area) * 2000. +*/
+        (
+            unevenness(&level, area) * 1.5 - avg_stone * 5. + distance(params.pos) / 100.,
+            avg_stone,
+        )
+    };
+
+    // Dig into the land behind the lot, away from the road
+    let lot_params = |lot: Lot| Params {
+        pos: lot.front(7, 7).center(),
+        dir: IVec2::from(lot.facing.rotated(2)).as_vec2().to_angle(),
+    };
+    // Quarries go where the stone is, further out than other buildings
+    let lot = lots.take_best(&level, reachability, 3 * TOWN_LOT_REACH, |lot| {
+        let params = lot_params(lot);
+        if too_close(params.pos) {
+            return f32::INFINITY;
+        }
+        match cost(params) {
+            (cost, avg_stone) if avg_stone >= MIN_LOT_STONE => cost,
+            _ => f32::INFINITY,
+        }
+    });
+    let params = if let Some(lot) = lot {
+        lot_params(lot)
+    } else if let Some(params) = optimize(
         Params {
-            pos: center.single()?.0.block().truncate(),
+            pos: center.0.block().truncate(),
             dir: rand(0. ..2. * PI),
         },
         |params, temperature| {
@@ -95,56 +173,23 @@ pub fn plan_quarry_sys(
                 return f32::INFINITY;
             }
 
-            let mut distance = level.reachability[params.pos] as f32 - 650.;
-            // Penalize quarries near city center
-            if distance < 0. {
-                distance *= -5.
-            }
-
             // At high temperatures, just get the broad strokes
             if temperature > 0.2 {
                 let probe_pos = params.pos + (params.dir_vec2() * 8.).as_ivec2();
-                if !level.area().contains(probe_pos)
-                    | others
-                        .iter()
-                        .any(|other| (other - params.pos).abs().element_sum() < 24)
-                {
+                if !level.area().contains(probe_pos) | too_close(params.pos) {
                     return f32::INFINITY;
                 }
                 return (level.height[params.pos] - level.height[probe_pos] + 100) as f32
-                    + distance / 50.;
+                    + distance(params.pos) / 50.;
             }
 
-            if !level.free(params.base_area()) || !level.free(params.probed_mining_area()) {
-                return f32::INFINITY;
-            }
-            // TODO: determine floor height here, weighed by towards lower points along border
-            let avg_start_height = level.height.average(params.base_area()) as i32;
-
-            let mut stone = 0;
-            let mut columns = 0;
-            for column in params.probed_mining_area() {
-                columns += 1;
-                for z in avg_start_height..avg_start_height + 15 {
-                    match level(column.extend(z)) {
-                        block if quarryable(block) => stone += 1,
-                        other if !other.solid() => break,
-                        _ => (),
-                    }
-                }
-            }
-            let avg_stone = stone as f32 / columns as f32;
-
-            let area = Rect::new_centered(params.pos, IVec2::splat(7));
-            if area.into_iter().any(|c| level.water[c].is_some()) {
-                return f32::INFINITY;
-            }
-            /*wateryness(&level, area) * 2000. +*/
-            unevenness(&level, area) * 1.5 - avg_stone * 5. + distance / 100.
+            cost(*params).0
         },
         400,
         25,
-    ) else {
+    ) {
+        params
+    } else {
         eprintln!("failed to place quarry");
         return Ok(());
     };
@@ -1,3 +1,4 @@
+use std::cmp::Reverse;
 use std::f32::consts::PI;
 
 use crate::*;
@@ -5,12 +6,15 @@ use itertools::Itertools;
 use sim::*;
 
 use self::{
+    bridge::plan_bridges,
     desire_lines::{add_desire_line, DesireLines},
     pathfind::{pathfind, PathingNode},
+    shipping::water_courses,
     trees::{Tree, TreeGen, TreeState, Trees},
 };
 
 /// Direction: From center outwards
+/// Paved roads start where they branch off the existing network
 #[derive(Resource)]
 pub struct Roads(pub Vec<VecDeque<PathingNode>>);
 
@@ -19,13 +23,19 @@ pub fn init_roads_sys(
     mut tree_map: ResMut<Trees>,
     mut level: ResMut<Level>,
     mut dl: ResMut<DesireLines>,
-    city_center: Query<&Pos, With<CityCenter>>,
-) -> Result<()> {
-    let center = city_center.single()?.block().truncate();
-    let ray_start = center.as_vec2() * 0.5 + level.area().center_vec2() * 0.5;
-    let count = 5;
-    let paths = (0..count)
-        .map(|i| {
+    city_centers: Query<(Entity, &Pos), With<CityCenter>>,
+) {
+    let centers = city_centers
+        .iter()
+        .sorted_by_key(|(e, _)| *e)
+        .map(|(_, pos)| pos.block().truncate())
+        .collect_vec();
+    let mut paths = Vec::new();
+    for (town, &center) in centers.iter().enumerate() {
+        let ray_start = center.as_vec2() * 0.5 + level.area().center_vec2() * 0.5;
+        // Smaller towns get fewer roads
+        let count = if town == 0 { 5 } else { 3 };
+        for i in 0..count {
             // Find path
             let angle = (i as f32 + rand(0. ..0.4)) * 2. * PI / count as f32;
             let direction = vec2(angle.cos(), angle.sin());
@@ -36,27 +46,32 @@ pub fn init_roads_sys(
             pos += direction * 5.;
             let start = level.ground(center) + IVec3::Z;
             let end = level.ground(pos.block()) + IVec3::Z;
-            let path = pathfind(&level, start, end, 10);
+            paths.push(build_road(&mut level, &mut dl, start, end));
+        }
+    }
 
-            // Build road
-            for node in &path.path {
-                for (x_off, y_off) in (-1..=1).cartesian_product(-1..=1) {
-                    let offset = ivec2(x_off, y_off);
-                    if !node.boat {
-                        level.blocked[node.pos.truncate() + offset] = Street;
-                    }
-                    for _ in 0..10 {
-                        add_desire_line(&mut level, &mut dl, node.pos + offset.extend(-1));
-                    }
-                }
-            }
-            path.path
+    // Link each town to the closest one founded before it
+    // These don't lead out of the map, so they aren't added to `Roads`
+    let mut links = (1..centers.len())
+        .map(|i| {
+            let other = centers[..i]
+                .iter()
+                .min_by_key(|other| other.distance_squared(centers[i]))
+                .unwrap();
+            let start = level.ground(centers[i]) + IVec3::Z;
+            let end = level.ground(*other) + IVec3::Z;
+            build_road(&mut level, &mut dl, start, end)
         })
         .collect_vec();
 
+    let courses = water_courses(&level);
+    for path in paths.iter_mut().chain(&mut links) {
+        plan_bridges(&mut commands, &mut level, &courses, path);
+    }
+
     // Line with trees
     // TODO: Hedges, other trees
-    for path in &paths {
+    for path in paths.iter().chain(&links) {
         let mut points_left = Vec::new();
         let mut points_right = Vec::new();
         for i in 50.. {
@@ -118,5 +133,162 @@ pub fn init_roads_sys(
     }
 
     commands.insert_resource(Roads(paths));
-    Ok(())
+}
+
+fn build_road(
+    level: &mut Level,
+    dl: &mut DesireLines,
+    start: IVec3,
+    end: IVec3,
+) -> VecDeque<PathingNode> {
+    let path = pathfind(level, start, end, 10);
+    for node in &path.path {
+        for (x_off, y_off) in (-1..=1).cartesian_product(-1..=1) {
+            let offset = ivec2(x_off, y_off);
+            if !node.boat {
+                level.blocked[node.pos.truncate() + offset] = Street;
+            }
+            for _ in 0..10 {
+                add_desire_line(level, dl, node.pos + offset.extend(-1));
+            }
+        }
+    }
+    path.path
+}
+
+/// How worn a corridor has to be before it gets paved
+const PAVE_WEAR: i32 = 20;
+/// Shorter corridors are left as footpaths
+const MIN_SEGMENT_LENGTH: u32 = 12;
+const MAX_SEGMENTS_PER_PLANNING: usize = 4;
+
+/// Turns the most worn corridors branching off the street network into paved roads
+pub fn plan_paving_sys(
+    mut commands: Commands,
+    tick: Res<CurrentTick>,
+    mut level: ResMut<Level>,
+    dl: Res<DesireLines>,
+    mut roads: ResMut<Roads>,
+) {
+    if tick.0 % 2000 != 1000 {
+        return;
+    }
+    let worn = |level: &Level, column: IVec2| {
+        level.area().contains(column)
+            && (dl[column] >= PAVE_WEAR)
+            && (level.blocked[column] == Free)
+            && level.water[column].is_none()
+    };
+
+    // Follow the corridors outward from the junctions with the network
+    let mut parents = HashMap::default();
+    let mut queue = VecDeque::new();
+    for column in level.area() {
+        if worn(&level, column)
+            && NEIGHBORS_2D
+                .iter()
+                .any(|off| level.blocked[column + *off] == Street)
+        {
+            parents.insert(column, (None, 0));
+            queue.push_back(column);
+        }
+    }
+    while let Some(column) = queue.pop_front() {
+        let distance = parents[&column].1;
+        for off in NEIGHBORS_2D {
+            let next = column + off;
+            if worn(&level, next) && !parents.contains_key(&next) {
+                parents.insert(next, (Some(column), distance + 1));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    // Trace segments back from the farthest ends, each joining either
+    // the network or a segment traced before it
+    let ends = parents
+        .iter()
+        .map(|(&column, &(_, distance))| (distance, column))
+        .filter(|&(distance, _)| distance >= MIN_SEGMENT_LENGTH)
+        .sorted_by_key(|&(distance, column)| (Reverse(distance), column.x, column.y))
+        .collect_vec();
+    let mut covered = HashSet::default();
+    let mut segments = 0;
+    for (_, end) in ends {
+        if segments == MAX_SEGMENTS_PER_PLANNING {
+            break;
+        }
+        if covered.contains(&end) {
+            continue;
+        }
+        let mut columns = vec![end];
+        while let (Some(parent), _) = parents[columns.last().unwrap()] {
+            if covered.contains(&parent) {
+                break;
+            }
+            columns.push(parent);
+        }
+        for column in &columns {
+            covered.extend(Rect::new_centered(*column, IVec2::splat(5)));
+        }
+        if (columns.len() as u32) < MIN_SEGMENT_LENGTH {
+            continue;
+        }
+        columns.reverse();
+        let cons = pave(&mut level, &columns);
+        if !cons.is_empty() {
+            commands.spawn((
+                Pos((level.ground(columns[0]) + IVec3::Z).as_vec3()),
+                ConstructionSite::new(cons),
+                Infrastructure,
+            ));
+        }
+        roads.0.push(
+            columns
+                .iter()
+                .map(|&column| PathingNode {
+                    pos: level.ground(column) + IVec3::Z,
+                    boat: false,
+                })
+                .collect(),
+        );
+        segments += 1;
+    }
+}
+
+fn pave(level: &mut Level, columns: &[IVec2]) -> ConsList {
+    use Biome::*;
+    // Long roads can lead into other biomes
+    let material = match level.biome[columns[columns.len() / 2]] {
+        Snowy | Taiga | Swamp | MangroveSwamp => Gravel,
+        Desert | Mesa | Savanna | Beach => Full(Cobble),
+        _ => Path,
+    };
+    let cursor = level.recording_cursor();
+    for &center in columns {
+        for (x_off, y_off) in (-1..=1).cartesian_product(-1..=1) {
+            let column = center + ivec2(x_off, y_off);
+            if !level.area().contains(column) | (level.blocked[column] != Free) {
+                continue;
+            }
+            level.blocked[column] = Street;
+            let pos = level.ground(column);
+            if !matches!(
+                level(pos),
+                Grass | Podzol | Dirt | CoarseDirt | PackedMud | Sand | SnowBlock | PowderedSnow
+            ) {
+                continue;
+            }
+            if matches!(
+                level(pos + IVec3::Z),
+                SmallPlant(..) | TallPlant(..) | SnowLayer
+            ) {
+                level(pos + IVec3::Z, Air)
+            }
+            if level(pos + IVec3::Z) == Air {
+                level(pos, material)
+            }
+        }
+    }
+    level.pop_recording(cursor).map(ConsItem::Set).collect()
 }
@@ -1,9 +1,15 @@
+pub mod bridge;
 pub mod building_plan;
+pub mod chronicle;
 pub mod construction;
 pub mod desire_lines;
+pub mod farm;
 pub mod infinite_sim;
+pub mod kiln;
 pub mod logistics;
+pub mod lots;
 pub mod lumberjack;
+pub mod metrics;
 pub mod quarry;
 pub mod roads;
 mod social;
@@ -13,21 +19,29 @@ mod villager;
 use std::collections::VecDeque;
 use std::sync::OnceLock;
 
-use crate::desire_lines::{DesireLines, desire_lines_sys};
+use crate::chronicle::{Chronicle, chronicle_sys, write_chronicle};
+use crate::debug_image::timelapse_sys;
+use crate::desire_lines::{DesireLines, desire_lines_sys, plan_stairs_sys};
+use crate::export::Export;
+use crate::farm::{plan_field_sys, test_build_field_sys};
 use crate::goods::*;
+use crate::kiln::{plan_kiln_sys, test_build_kiln_sys};
 use crate::lang::Lang;
+use crate::lots::{Lots, subdivide_lots_sys};
 use crate::lumberjack::{plan_lumberjack_sys, test_build_lumberjack_sys};
 use crate::market::{init_stalls_sys, plan_stalls_sys, upgrade_plaza_sys};
+use crate::metrics::{Metrics, metrics_sys};
 use crate::names::make_town_name;
 use crate::optimize::optimize;
 use crate::pathfind::reachability_2d_from;
 use crate::quarry::{plan_quarry_sys, test_build_quarry_sys};
-use crate::roads::init_roads_sys;
+use crate::replay::*;
+use crate::roads::{init_roads_sys, plan_paving_sys};
+use crate::route_planner::RoutePlanner;
 use crate::sim::social::{Arrival, ArrivalKind};
 use crate::sim::storage_pile::update_pile_visuals;
 use crate::trees::{grow_trees_sys, init_trees_sys, spawn_trees_sys};
 use crate::*;
-use crate::{pathfind::pathfind, replay::*};
 use bevy_ecs::schedule::ExecutorKind;
 use bevy_ecs::system::RunSystemOnce;
 use building_plan::*;
@@ -42,8 +56,53 @@ pub use villager::*;
 use bevy_derive::{Deref, DerefMut};
 pub use bevy_ecs::prelude::*;
 use bevy_math::Vec2Swizzles;
+use itertools::Itertools;
+use rayon::prelude::*;
 
-pub fn sim(mut level: Level, config: Config) {
+pub fn sim(level: Level, config: Config) -> Result<()> {
+    let mut world = simulate(level, config)?;
+
+    let level = world.remove_resource::<Level>().unwrap();
+
+    world.resource::<Lang>().write_blurbs(&level.path);
+    if world
+        .resource::<Config>()
+        .metrics_interval
+        .is_some_and(|interval| interval > 0)
+    {
+        world.resource::<Metrics>().write(&level.path);
+    }
+
+    let config = world.resource::<Config>();
+    let (schematic_path, structure_path) = (
+        config.export_schematic.clone(),
+        config.export_structure.clone(),
+    );
+    if schematic_path.is_some() | structure_path.is_some() {
+        let mut export = Export::new(&level);
+        if world.resource::<Config>().export_villagers {
+            export.add_villagers(&mut world);
+        }
+        if let Some(path) = schematic_path {
+            export.save_schematic(path);
+        }
+        if let Some(path) = structure_path {
+            export.save_structure(path);
+        }
+    }
+
+    if world.resource::<Config>().no_replay {
+        level.save();
+    } else {
+        let replay = world.remove_resource::<Replay>().unwrap();
+        rayon::spawn(move || level.save_metadata());
+        replay.finish();
+    }
+    Ok(())
+}
+
+/// Runs the simulation without writing the world or the replay datapack
+pub fn simulate(mut level: Level, config: Config) -> Result<World> {
     if config.show_level_borders {
         for column in level.area().border() {
             let z = level.height[column];
@@ -51,22 +110,44 @@ pub fn sim(mut level: Level, config: Config) {
         }
     }
 
-    let city_center = choose_starting_area(&level);
+    let town_areas = choose_starting_areas(&level);
+    if town_areas.is_empty() {
+        return Err("No suitable area for a town found".into());
+    }
     let mut replay = Replay::new(&level);
-    replay.say(
-        &format!("{}: Founding of {}", rand(1400..1550), make_town_name()),
-        Yellow,
-    );
+    replay.set_collapse_into_init(config.replay_start_tick > 0);
+    let town_names = town_areas.iter().map(|_| make_town_name()).collect_vec();
+    let mut chronicle = Chronicle::new(town_names[0].clone(), rand(1400..1550));
+    for name in town_names {
+        chronicle.record_founding(&mut replay, &name);
+    }
 
     let mut world = World::new();
     world.insert_resource(config);
     world.init_resource::<CurrentTick>();
+    world.insert_resource(chronicle);
 
-    let city_center_pos = level.ground(city_center.center());
-    CENTER_BIOME.get_or_init(|| level.biome[city_center.center()]);
-    (level.blocked)(city_center, Street);
-    world.spawn((Pos(city_center_pos.as_vec3()), CityCenter(city_center)));
-    level.reachability = reachability_2d_from(&level, city_center.center());
+    let city_center_pos = level.ground(town_areas[0].center());
+    CENTER_BIOME.get_or_init(|| level.biome[town_areas[0].center()]);
+    for &area in &town_areas {
+        (level.blocked)(area, Street);
+    }
+    let reachabilities = town_areas
+        .par_iter()
+        .map(|area| reachability_2d_from(&level, area.center()))
+        .collect::<Vec<_>>();
+    let mut reachability = level.column_map(u32::MAX);
+    for (&area, town_reachability) in town_areas.iter().zip(reachabilities) {
+        for (total, town) in reachability.data.iter_mut().zip(&town_reachability.data) {
+            *total = (*total).min(*town);
+        }
+        world.spawn((
+            Pos(level.ground(area.center()).as_vec3()),
+            CityCenter(area),
+            Reachability(town_reachability),
+        ));
+    }
+    level.reachability = reachability;
 
     world.init_resource::<Lang>();
 
@@ -90,16 +171,17 @@ pub fn sim(mut level: Level, config: Config) {
     world.insert_resource(level);
 
     world.init_resource::<DesireLines>();
+    world.init_resource::<Metrics>();
+    world.init_resource::<RoutePlanner>();
+    world.init_resource::<Lots>();
 
     world
         .run_system_once(detect_existing_buildings_sys)
         .unwrap();
     world.run_system_once(init_trees_sys).unwrap();
-    world
-        .run_system_once::<_, (), _>(starting_resources_sys)
-        .unwrap();
-    world.run_system_once::<_, (), _>(init_stalls_sys).unwrap();
-    world.run_system_once::<_, (), _>(init_roads_sys).unwrap();
+    world.run_system_once(starting_resources_sys).unwrap();
+    world.run_system_once(init_stalls_sys).unwrap();
+    world.run_system_once(init_roads_sys).unwrap();
 
     let mut sched = Schedule::default();
     // Because the systems are extremely lightweight, running them on a single thread
@@ -120,34 +202,68 @@ pub fn sim(mut level: Level, config: Config) {
                 update_piles_sys,
             ),
             (
-                lumberjack::assign_worker_sys,
-                lumberjack::make_lumber_piles_sys,
-                lumberjack::work_sys,
-                lumberjack::chop_sys,
-            ),
+                (
+                    lumberjack::assign_worker_sys,
+                    lumberjack::make_lumber_piles_sys,
+                    lumberjack::work_sys,
+                    lumberjack::chop_sys,
+                ),
+                (
+                    quarry::assign_worker_sys,
+                    quarry::make_stone_pile_sys,
+                    quarry::work_sys,
+                    quarry::quarry_rotation_sys,
+                    quarry::update_quarry_rotation_sys,
+                ),
+                (
+                    farm::assign_worker_sys,
+                    farm::make_food_pile_sys,
+                    farm::work_sys,
+                    farm::grow_crops_sys,
+                    farm::stock_pantries_sys,
+                    farm::eat_sys,
+                ),
+                (
+                    kiln::assign_worker_sys,
+                    kiln::request_soil_sys,
+                    kiln::work_sys,
+                    kiln::fire_sys,
+                ),
+            )
+                .chain(),
+            // After everything that can issue move tasks
+            resolve_paths_sys,
             (
-                quarry::assign_worker_sys,
-                quarry::make_stone_pile_sys,
-                quarry::work_sys,
-                quarry::quarry_rotation_sys,
-                quarry::update_quarry_rotation_sys,
-            ),
-            (
-                plan_house_sys,
-                plan_lumberjack_sys,
-                plan_quarry_sys,
-                plan_stalls_sys,
-            ),
+                subdivide_lots_sys,
+                (
+                    plan_house_sys,
+                    plan_lumberjack_sys,
+                    plan_quarry_sys,
+                    plan_kiln_sys,
+                    plan_field_sys,
+                    plan_stalls_sys,
+                ),
+            )
+                .chain(),
             assign_builds_sys,
             (
-                test_build_house_sys,
+                // Both use bricks, the plaza has to see the house's site
+                (test_build_house_sys, upgrade_plaza_sys).chain(),
                 test_build_lumberjack_sys,
                 test_build_quarry_sys,
-                upgrade_plaza_sys,
+                test_build_kiln_sys,
+                test_build_field_sys,
                 hitching_post_sys,
+                garden_sys,
             ),
             new_construction_site_sys,
+            bridge::finish_bridges_sys,
             desire_lines_sys,
+            plan_stairs_sys,
+            plan_paving_sys,
+            timelapse_sys,
+            metrics_sys,
+            chronicle_sys,
             tick_replay_sys,
             |mut tick: ResMut<CurrentTick>| tick.0 += 1,
             World::clear_trackers,
@@ -156,28 +272,23 @@ pub fn sim(mut level: Level, config: Config) {
     );
     world.add_observer(update_pile_visuals);
 
-    for _ in 0..world.resource::<Config>().ticks {
+    let replay_start_tick = world.resource::<Config>().replay_start_tick;
+    for tick in 0..world.resource::<Config>().ticks {
+        if tick == replay_start_tick {
+            world.resource_mut::<Replay>().set_collapse_into_init(false);
+        }
         sched.run(&mut world);
         world.increment_change_tick();
     }
+    world.resource_mut::<Replay>().set_collapse_into_init(false);
     world.resource_mut::<Replay>().say("Replay complete", Gray);
     world
         .resource_mut::<Replay>()
         .command("scoreboard players set sim speed 1".into());
     world.run_system_once(flush_unfinished_changes).unwrap();
+    world.run_system_once(write_chronicle).unwrap();
     infinite_sim::generate(&mut world);
-
-    let level = world.remove_resource::<Level>().unwrap();
-
-    world.resource::<Lang>().write_blurbs(&level.path);
-
-    if world.resource::<Config>().no_replay {
-        level.debug_save();
-    } else {
-        let replay = world.remove_resource::<Replay>().unwrap();
-        rayon::spawn(move || level.save_metadata());
-        replay.finish();
-    }
+    Ok(world)
 }
 
 #[derive(Resource, Default, Deref, DerefMut)]
@@ -186,6 +297,25 @@ pub struct CurrentTick(pub i32);
 #[derive(Component, Deref)]
 pub struct CityCenter(Rect);
 
+/// Walking cost from a town center
+#[derive(Component, Deref)]
+pub struct Reachability(ColumnMap<u32>);
+
+impl Reachability {
+    /// Whether no other town is closer to the column
+    pub fn serves(&self, level: &Level, column: IVec2) -> bool {
+        self[column] <= level.reachability[column]
+    }
+}
+
+/// The town center a villager belongs to
+#[derive(Component, Deref, Clone, Copy)]
+pub struct Town(pub Entity);
+
+/// A workplace that has its worker
+#[derive(Component)]
+pub struct Staffed;
+
 /// For convenience
 static CENTER_BIOME: OnceLock<Biome> = OnceLock::new();
 pub fn center_biome() -> Biome {
@@ -209,12 +339,28 @@ fn starting_resources_sys(
     mut commands: Commands,
     mut level: ResMut<Level>,
     mut untree: Untree,
-    city_center: Query<(Entity, &Pos), With<CityCenter>>,
-) -> Result<()> {
-    let (center, pos) = city_center.single()?;
+    city_centers: Query<(Entity, &Pos), With<CityCenter>>,
+) {
+    for (center, pos) in &city_centers {
+        starting_resources(
+            &mut commands,
+            &mut level,
+            &mut untree,
+            center,
+            pos.truncate(),
+        );
+    }
+}
+
+fn starting_resources(
+    commands: &mut Commands,
+    level: &mut Level,
+    untree: &mut Untree,
+    center: Entity,
+    pos: Vec2,
+) {
     for _ in 0..6 {
-        let (pos, area, params) =
-            LumberPile::make(&mut level, &mut untree, pos.truncate(), pos.truncate());
+        let (pos, area, params) = LumberPile::make(level, untree, pos, pos);
 
         let goods = {
             let mut stock = Goods::default();
@@ -234,7 +380,7 @@ fn starting_resources_sys(
         ));
     }
     for _ in 0..6 {
-        let (pos, area, params) = StonePile::make(&mut level, &mut untree, pos.truncate());
+        let (pos, area, params) = StonePile::make(level, untree, pos);
 
         let goods = {
             let mut stock = Goods::default();
@@ -264,22 +410,25 @@ fn starting_resources_sys(
     commands
         .entity(center)
         .insert((OutPile::default(), Pile::new(starting_resources, 1)));
-    Ok(())
 }
 
 fn spawn_villagers_sys(
     mut commands: Commands,
     level: Res<Level>,
     tick: Res<CurrentTick>,
-    city_center: Query<&Pos, With<CityCenter>>,
+    city_centers: Query<(Entity, &Pos), With<CityCenter>>,
     config: Res<Config>,
-) -> Result<()> {
+) {
     if (tick.0 < config.villagers * 4) & (tick.0 % 4 == 0) {
-        let column = city_center.single()?.truncate() + vec2(rand(-5. ..5.), rand(-5. ..5.));
+        // Distribute the villagers evenly among the towns
+        let centers = city_centers.iter().sorted_by_key(|(e, _)| *e).collect_vec();
+        let (town, center) = centers[(tick.0 / 4) as usize % centers.len()];
+        let column = center.truncate() + vec2(rand(-5. ..5.), rand(-5. ..5.));
         commands.spawn((
             Id::default(),
             Villager::default(),
             Jobless,
+            Town(town),
             Pos(level.ground(column.block()).as_vec3() + Vec3::Z),
             Arrival {
                 tick: tick.0,
@@ -287,7 +436,6 @@ fn spawn_villagers_sys(
             },
         ));
     }
-    Ok(())
 }
 
 fn flush_unfinished_changes(
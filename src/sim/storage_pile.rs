@@ -83,55 +83,78 @@ pub struct StonePile {
 
 impl StonePile {
     pub fn make(level: &mut Level, untree: &mut Untree, target: Vec2) -> (Vec3, Rect, Self) {
-        let area = optimize(
-            Rect {
-                min: target.block(),
-                max: target.block() + ivec2(rand(3..=4), rand(3..=4)),
-            },
-            |area, temperature| {
-                if rand(0.3) {
-                    *area = Rect {
-                        min: area.center(),
-                        max: area.center() + ivec2(rand(3..=4), rand(3..=4)),
-                    }
-                } else {
-                    let max_move = (20. * temperature) as i32;
-                    *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
-                }
-                if !level.free(*area) | (wateryness(level, *area) > 0.) {
-                    return f32::INFINITY;
-                }
-                // TODO: use actual pathfinding distance (when there are proper pathable workplaces)
-                let worker_distance = target.distance(area.center_vec2()) / 20.;
-                let size_bonus = area.total() as f32 * 4.;
-                worker_distance + unevenness(level, *area) * 1. - size_bonus
-            },
-            100,
-            5,
-        )
-        // TODO
-        .unwrap();
+        let (pos, area, volume) = make_heap(level, untree, target);
+        (pos, area, StonePile { volume })
+    }
 
-        untree.remove_trees(level, area);
+    pub fn max(&self) -> f32 {
+        self.volume.volume() as f32
+    }
+}
 
-        let z = level.height.average(area.border()) as i32 + 1;
-        (level.height)(area, z - 1);
-        level.fill_at(area, z - 1, PackedMud);
-        (level.blocked)(area, Blocked);
-        (
-            area.center_vec2().extend(z as f32),
-            area,
-            StonePile {
-                volume: Cuboid::new(area.min.extend(z), area.max.extend(z + 2)),
-            },
-        )
+#[derive(Component)]
+pub struct FoodPile {
+    pub volume: Cuboid,
+}
+
+impl FoodPile {
+    /// Food per hay bale
+    const PER_BLOCK: f32 = 4.;
+
+    pub fn make(level: &mut Level, untree: &mut Untree, target: Vec2) -> (Vec3, Rect, Self) {
+        let (pos, area, volume) = make_heap(level, untree, target);
+        (pos, area, FoodPile { volume })
     }
 
     pub fn max(&self) -> f32 {
-        self.volume.volume() as f32
+        self.volume.volume() as f32 * Self::PER_BLOCK
     }
 }
 
+/// Small area for piles of stuff that can be stacked freely
+fn make_heap(level: &mut Level, untree: &mut Untree, target: Vec2) -> (Vec3, Rect, Cuboid) {
+    let area = optimize(
+        Rect {
+            min: target.block(),
+            max: target.block() + ivec2(rand(3..=4), rand(3..=4)),
+        },
+        |area, temperature| {
+            if rand(0.3) {
+                *area = Rect {
+                    min: area.center(),
+                    max: area.center() + ivec2(rand(3..=4), rand(3..=4)),
+                }
+            } else {
+                let max_move = (20. * temperature) as i32;
+                *area += ivec2(rand(-max_move..=max_move), rand(-max_move..=max_move));
+            }
+            if !level.free(*area) | (wateryness(level, *area) > 0.) {
+                return f32::INFINITY;
+            }
+            // TODO: use actual pathfinding distance (when there are proper pathable workplaces)
+            let worker_distance = target.distance(area.center_vec2()) / 20.;
+            let size_bonus = area.total() as f32 * 4.;
+            worker_distance + unevenness(level, *area) * 1. - size_bonus
+        },
+        100,
+        5,
+    )
+    // TODO
+    .unwrap();
+
+    untree.remove_trees(level, area);
+
+    let z = level.height.average(area.border()) as i32 + 1;
+    (level.height)(area, z - 1);
+    level.fill_at(area, z - 1, PackedMud);
+    (level.blocked)(area, Blocked);
+    (
+        area.center_vec2().extend(z as f32),
+        area,
+        Cuboid::new(area.min.extend(z), area.max.extend(z + 2)),
+    )
+}
+
 #[derive(EntityEvent)]
 pub struct UpdatePileVisuals {
     pub entity: Entity,
@@ -143,6 +166,7 @@ pub fn update_pile_visuals(
     query: Query<(&Pos, &Pile)>,
     lumber: Query<&LumberPile>,
     stone: Query<&StonePile>,
+    food: Query<&FoodPile>,
 ) {
     let Ok((pos, pile)) = query.get(trigger.event().entity) else {
         return;
@@ -226,4 +250,12 @@ pub fn update_pile_visuals(
             leftover -= 1.;
         }
     }
+
+    if let Ok(foodpile) = food.get(trigger.event().entity) {
+        let mut leftover = pile.available(Good::Food, 0);
+        for pos in foodpile.volume {
+            level(pos, if leftover > 0. { Hay } else { Air });
+            leftover -= FoodPile::PER_BLOCK;
+        }
+    }
 }
@@ -1,5 +1,7 @@
 use bevy_ecs::prelude::*;
 
+use crate::sim::farm::Pantry;
+use crate::sim::kiln::BrickKiln;
 use crate::sim::social::make_name;
 use crate::sim::*;
 use crate::*;
@@ -33,8 +35,9 @@ pub struct PlaceTask(pub ConsList);
 
 pub fn assign_work_sys(
     mut commands: Commands,
+    level: Res<Level>,
     idle: Query<
-        (Entity, &Pos),
+        (Entity, &Pos, &Town),
         (
             With<Villager>,
             With<Jobless>,
@@ -42,16 +45,30 @@ pub fn assign_work_sys(
             Without<BuildTask>,
         ),
     >,
-    mut out_piles: Query<(Entity, &Pos, &mut OutPile, &mut Pile)>,
+    mut out_piles: Query<(
+        Entity,
+        &Pos,
+        &mut OutPile,
+        &mut Pile,
+        Has<Pantry>,
+        Has<BrickKiln>,
+    )>,
     mut in_piles: Query<(Entity, &Pos, &mut InPile)>,
     mut construction_sites: Query<(Entity, &Pos, &mut ConstructionSite, &Pile), Without<OutPile>>,
-) {
-    for (vill, vil_pos) in &idle {
+    towns: Query<&Reachability, With<CityCenter>>,
+) -> Result<()> {
+    for (vill, vil_pos, town) in &idle {
+        // Stay within the own town
+        let reachability = towns.get(town.0)?;
+        let ours = |pos: &Pos| reachability.serves(&level, pos.block().truncate());
+
         // Construction
         if let Some((building, pos, mut site, _)) = construction_sites
             .iter_mut()
             .filter(|(_, site_pos, site, pile)| {
-                site.has_materials(pile, min_walk_ticks(vil_pos.0, site_pos.0)) & !site.has_builder
+                site.has_materials(pile, min_walk_ticks(vil_pos.0, site_pos.0))
+                    & !site.has_builder
+                    & ours(site_pos)
             })
             .min_by_key(|(_, pos, _, _)| pos.distance_squared(vil_pos.0) as u32)
         {
@@ -65,11 +82,20 @@ pub fn assign_work_sys(
         // Transport
         if let Some((_, task)) = out_piles
             .iter_mut()
-            .filter_map(|(out_entity, out_pos, out_pile, pile)| {
+            .filter(|(_, out_pos, ..)| ours(out_pos))
+            .filter_map(|(out_entity, out_pos, out_pile, pile, pantry, kiln)| {
                 let min_ticks = min_walk_ticks(vil_pos.0, out_pos.0);
                 let mut best_score = f32::INFINITY;
                 let mut task = None;
                 for good in pile.goods.keys() {
+                    // Don't take away people's food
+                    if pantry & (*good == Good::Food) {
+                        continue;
+                    }
+                    // Nor the soil waiting to be fired
+                    if kiln & (*good == Good::Soil) {
+                        continue;
+                    }
                     let amount = pile.available(*good, min_ticks)
                         - out_pile.reserved.get(good).copied().unwrap_or(0.);
                     if amount <= 0. {
@@ -78,6 +104,7 @@ pub fn assign_work_sys(
                     for (in_entity, in_pos, in_pile) in &mut in_piles {
                         if let Some(&requested) = in_pile.requested.get(good)
                             && requested > 0.
+                            && ours(in_pos)
                         {
                             if let Some(priority) = in_pile.priority
                                 && priority != *good
@@ -127,6 +154,7 @@ pub fn assign_work_sys(
             commands.entity(vill).insert(task);
         }
     }
+    Ok(())
 }
 
 pub fn place_sys(
@@ -0,0 +1,267 @@
+//! Adapts input and output to the Minecraft version of the world.
+//! Internally, blockstates and snbt use the format of [`DATA_VERSION`].
+
+use std::{borrow::Cow, fmt::Display, path::Path, sync::OnceLock};
+
+use itertools::Itertools;
+use nbt::{CompoundTag, Tag};
+
+use crate::*;
+
+static VERSION: OnceLock<Version> = OnceLock::new();
+
+/// Data version of the world
+pub fn version() -> Version {
+    VERSION.get().copied().unwrap_or(Version(DATA_VERSION))
+}
+
+/// Reads the version from level.dat. Only the first call has an effect.
+pub fn init_version(level_path: &Path) {
+    let version = (|| {
+        let mut file = std::fs::File::open(level_path.join("level.dat")).ok()?;
+        let nbt = nbt::decode::read_gzip_compound_tag(&mut file).ok()?;
+        nbt.get_compound_tag("Data")
+            .ok()?
+            .get_i32("DataVersion")
+            .ok()
+    })()
+    .unwrap_or(DATA_VERSION);
+    if !(Version::OLDEST.0..=DATA_VERSION).contains(&version) {
+        eprintln!("Using data version {version}, which is not supported.");
+    }
+    VERSION.get_or_init(|| Version(version));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub i32);
+
+/// (version of the rename, old name, new name)
+const BLOCK_RENAMES: &[(Version, &str, &str)] = &[
+    (Version::V1_20_3, "grass", "short_grass"),
+    (Version::V1_21_9, "chain", "iron_chain"),
+];
+
+/// Gamerules renamed to snake_case in 1.21.11, (old name, new name)
+const GAMERULE_RENAMES: &[(&str, &str)] = &[
+    ("randomTickSpeed", "random_tick_speed"),
+    ("doMobSpawning", "spawn_mobs"),
+    ("mobGriefing", "mob_griefing"),
+    ("doTileDrops", "block_drops"),
+];
+
+struct PropertyRename {
+    version: Version,
+    block: &'static str,
+    old: &'static str,
+    new: &'static str,
+    /// (old, new), the first match is used in each direction
+    values: &'static [(&'static str, &'static str)],
+}
+
+const PROPERTY_RENAMES: &[PropertyRename] = &[PropertyRename {
+    version: Version::V1_21_5,
+    block: "creaking_heart",
+    old: "active",
+    new: "creaking_heart_state",
+    values: &[
+        ("true", "awake"),
+        ("false", "dormant"),
+        ("false", "uprooted"),
+    ],
+}];
+
+impl Version {
+    /// Function macros
+    pub const OLDEST: Version = Version::V1_20_2;
+    pub const V1_20_2: Version = Version(3578);
+    pub const V1_20_3: Version = Version(3698);
+    /// Item components
+    pub const V1_20_5: Version = Version(3837);
+    pub const V1_21: Version = Version(3953);
+    /// Boats of each wood type are separate entities
+    pub const V1_21_2: Version = Version(4080);
+    pub const V1_21_4: Version = Version(4189);
+    /// Entity equipment, text components as snbt
+    pub const V1_21_5: Version = Version(4325);
+    pub const V1_21_6: Version = Version(4435);
+    pub const V1_21_9: Version = Version(4554);
+    /// Snake_case gamerules
+    pub const V1_21_11: Version = Version(4671);
+
+    pub fn pack_format(self) -> i32 {
+        [
+            (Version::V1_21_9, 88),
+            (Version::V1_21_6, 80),
+            (Version::V1_21_5, 71),
+            (Version::V1_21_4, 61),
+            (Version::V1_21_2, 57),
+            (Version::V1_21, 48),
+            (Version::V1_20_5, 41),
+            (Version::V1_20_3, 26),
+        ]
+        .into_iter()
+        .find(|(version, _)| self >= *version)
+        // Oldest supported version
+        .map_or(18, |(_, format)| format)
+    }
+
+    /// Datapack folders were pluralized before 1.21
+    pub fn function_folder(self) -> &'static str {
+        if self >= Version::V1_21 {
+            "function"
+        } else {
+            "functions"
+        }
+    }
+
+    /// Converts a block name from this version to the current one
+    pub fn import_block_name(self, name: &str) -> &str {
+        BLOCK_RENAMES
+            .iter()
+            .find(|(version, old, _)| (self < *version) & (*old == name))
+            .map_or(name, |(_, _, new)| *new)
+    }
+
+    /// Converts the properties of a block (with its current name) from this version to the current one
+    pub fn import_properties(self, name: &str, props: &CompoundTag) -> CompoundTag {
+        let mut imported = CompoundTag::new();
+        for (prop, value) in props.iter() {
+            if let Some(rename) = PROPERTY_RENAMES.iter().find(|rename| {
+                (self < rename.version) & (rename.block == name) & (rename.old == prop.as_str())
+            }) && let Tag::String(value) = value
+            {
+                let value = rename
+                    .values
+                    .iter()
+                    .find(|(old, _)| *old == value.as_str())
+                    .map_or(value.as_str(), |(_, new)| *new);
+                imported.insert_str(rename.new, value);
+            } else {
+                imported.insert(prop.as_str(), value.clone());
+            }
+        }
+        imported
+    }
+
+    /// Converts a blockstate from the current version to this one
+    pub fn export_blockstate(self, mut blockstate: Blockstate) -> Blockstate {
+        let name = blockstate.0.clone();
+        if let Some((_, old, _)) = BLOCK_RENAMES
+            .iter()
+            .find(|(version, _, new)| (self < *version) & (*new == blockstate.0))
+        {
+            blockstate.0 = Cow::Borrowed(*old);
+        }
+        for (prop, value) in &mut blockstate.1 {
+            if let Some(rename) = PROPERTY_RENAMES.iter().find(|rename| {
+                (self < rename.version) & (name == rename.block) & (*prop == rename.new)
+            }) {
+                *prop = Cow::Borrowed(rename.old);
+                if let Some((old, _)) = rename.values.iter().find(|(_, new)| *value == *new) {
+                    *value = Cow::Borrowed(*old);
+                }
+            }
+        }
+        blockstate
+    }
+
+    /// Command to set a gamerule (with its current name), if it exists in this version
+    pub fn gamerule(self, rule: &str, value: impl Display) -> Option<String> {
+        let rule = if self >= Version::V1_21_11 {
+            rule
+        } else {
+            // Rules without an old name were added with the rename
+            GAMERULE_RENAMES
+                .iter()
+                .find(|(_, new)| *new == rule)
+                .map(|(old, _)| *old)?
+        };
+        Some(format!("gamerule {rule} {value}"))
+    }
+
+    /// Snbt fields of an item stack, without braces
+    pub fn item(self, id: &str, count: i32) -> String {
+        if self >= Version::V1_20_5 {
+            format!("id:\"{id}\",count:{count}")
+        } else {
+            format!("id:\"{id}\",Count:{count}b")
+        }
+    }
+
+    /// Snbt of a text component
+    pub fn text(self, text: &str) -> String {
+        let escaped = text.replace('\\', "\\\\").replace('"', "\\\"");
+        if self >= Version::V1_21_5 {
+            format!("\"{}\"", escaped.replace('\n', "\\n"))
+        } else {
+            // Json inside a string
+            format!(
+                "'\"{}\"'",
+                escaped
+                    .replace('\\', "\\\\")
+                    .replace('\n', "\\\\n")
+                    .replace('\'', "\\'")
+            )
+        }
+    }
+
+    /// Snbt fields of a written book, without braces
+    pub fn written_book(self, title: &str, author: &str, pages: &[String]) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let pages = pages.iter().map(|page| self.text(page)).join(",");
+        let content = format!(
+            "title:{},author:{},pages:[{pages}]",
+            quote(title),
+            quote(author)
+        );
+        if self >= Version::V1_20_5 {
+            format!(
+                "{},components:{{\"minecraft:written_book_content\":{{{content}}}}}",
+                self.item("written_book", 1)
+            )
+        } else {
+            format!("{},tag:{{{content}}}", self.item("written_book", 1))
+        }
+    }
+
+    /// Command to set or clear the item on an entity's head
+    pub fn set_head_item(self, entity: impl Display, item: Option<String>) -> String {
+        match (self >= Version::V1_21_5, item) {
+            (true, Some(item)) => {
+                format!("data modify entity {entity} equipment.head set value {item}")
+            }
+            (true, None) => format!("data remove entity {entity} equipment.head"),
+            (false, item) => format!(
+                "data modify entity {entity} ArmorItems[3] set value {}",
+                item.as_deref().unwrap_or("{}")
+            ),
+        }
+    }
+
+    /// Snbt field of a saddled mount
+    pub fn saddle(self) -> String {
+        if self >= Version::V1_21_5 {
+            format!("equipment:{{saddle:{{{}}}}}", self.item("saddle", 1))
+        } else {
+            format!("SaddleItem:{{{}}}", self.item("saddle", 1))
+        }
+    }
+
+    /// Snbt field of a mob leashed to a fence
+    pub fn leash(self, fence: IVec3) -> String {
+        if self >= Version::V1_21 {
+            format!("leash:[I;{},{},{}]", fence.x, fence.z, fence.y)
+        } else {
+            format!("Leash:{{X:{},Y:{},Z:{}}}", fence.x, fence.z, fence.y)
+        }
+    }
+
+    /// Entity id and snbt fields of a boat
+    pub fn boat(self, species: TreeSpecies) -> (String, String) {
+        if self >= Version::V1_21_2 {
+            (format!("{}_boat", species.to_str()), String::new())
+        } else {
+            ("boat".into(), format!(", Type:\"{}\"", species.to_str()))
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Runs the simulation on a small synthetic world and compares the results with golden values.
+//! This catches nondeterminism (e.g. from HashMap iteration, rayon or statics) as well as
+//! unintended changes in behavior. After intended changes, run with `BLESS=1` to update them.
+
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+use fnv::FnvHasher;
+
+use frightful_hobgoblin::construction::ConstructionSite;
+use frightful_hobgoblin::logistics::Pile;
+use frightful_hobgoblin::replay::Replay;
+use frightful_hobgoblin::sim::simulate;
+use frightful_hobgoblin::trees::Tree;
+use frightful_hobgoblin::*;
+use nanorand::WyRand;
+
+const GOLDEN_PATH: &str = "tests/golden/determinism.txt";
+
+#[test]
+fn golden_replay() {
+    let output_path = std::env::temp_dir().join("frightful_hobgoblin_determinism");
+    // Previous replays in the output would change ids
+    let _ = std::fs::remove_dir_all(&output_path);
+
+    let config: Config = toml::from_str(&format!(
+        r#"
+            path = ""
+            out_path = "{}"
+            synthetic_world = true
+            seed = 7
+            min_x = -40
+            max_x = 40
+            min_y = -40
+            max_y = 40
+            ticks = 3000
+            villagers = 8
+            no_replay = true
+        "#,
+        output_path.display()
+    ))
+    .unwrap();
+    RNG.set(WyRand::new_seed(config.seed.unwrap()));
+    let level = config.load_level();
+    let mut world = simulate(level, config).unwrap();
+
+    let mut summary = String::new();
+    writeln!(
+        summary,
+        "commands = {:016x}",
+        world.resource::<Replay>().command_hash()
+    )
+    .unwrap();
+
+    let level = world.resource::<Level>();
+    let mut hasher = FnvHasher::default();
+    for column in level.area() {
+        for z in -64..320 {
+            level(column.extend(z)).hash(&mut hasher);
+        }
+    }
+    writeln!(summary, "blocks = {:016x}", hasher.finish()).unwrap();
+
+    writeln!(summary, "entities = {}", world.entities().count_spawned()).unwrap();
+    let mut count = |name: &str, count: usize| writeln!(summary, "{name} = {count}").unwrap();
+    count("villagers", world.query::<&Villager>().iter(&world).count());
+    count("trees", world.query::<&Tree>().iter(&world).count());
+    count("piles", world.query::<&Pile>().iter(&world).count());
+    count(
+        "construction_sites",
+        world.query::<&ConstructionSite>().iter(&world).count(),
+    );
+
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::write(GOLDEN_PATH, &summary).unwrap();
+        return;
+    }
+    let golden = std::fs::read_to_string(GOLDEN_PATH).expect("Missing golden values");
+    assert_eq!(
+        summary, golden,
+        "Simulation results changed. If this is intended, rerun with BLESS=1"
+    );
+}